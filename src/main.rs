@@ -1,22 +1,397 @@
+use std::cell::RefCell;
 use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
 
+use rlox3::chunk_to_json;
+use rlox3::compile;
+use rlox3::deserialize_chunk;
+use rlox3::format_code;
+use rlox3::lint;
+use rlox3::optimize;
+use rlox3::parse;
 use rlox3::repl;
+use rlox3::run_language_server;
+use rlox3::run_script;
+use rlox3::serialize_chunk;
+use rlox3::Chunk;
+use rlox3::Debugger;
 use rlox3::InterpretError;
+use rlox3::ModuleError;
+use rlox3::ModuleLoader;
+use rlox3::Profiler;
+use rlox3::Scanner;
+use rlox3::Severity;
+use rlox3::StderrTracer;
+use rlox3::Vm;
+
+#[derive(Default)]
+struct Options {
+    command: Command,
+    filename: Option<String>,
+    files: Vec<String>,
+    output: Option<String>,
+    dump_bytecode: Option<DumpFormat>,
+    dump_ast: bool,
+    trace: bool,
+    profile: bool,
+    optimize: bool,
+    check: bool,
+}
+
+#[derive(Default, PartialEq)]
+enum Command {
+    #[default]
+    Run,
+    Debug,
+    Compile,
+    Fmt,
+    Lint,
+    Lsp,
+    Tokens,
+}
+
+enum DumpFormat {
+    Json,
+}
 
 fn main() -> Result<(), InterpretError> {
     let mut args = env::args();
     args.next();
 
-    let args: Vec<String> = args.collect();
+    let options = parse_options(args);
 
-    if args.len() > 1 {
-        println!("Usage: rlox [file]");
-        std::process::exit(64);
-    } else if let Some(_filename) = args.first() {
-        println!("TODO: Read file");
-    } else {
-        repl()?;
+    match (&options.command, options.filename.as_ref()) {
+        (Command::Fmt, _) => format_files(&options.files, options.check),
+        (Command::Lint, _) => lint_files(&options.files),
+        (Command::Lsp, _) => serve_lsp(),
+        (_, None) => {
+            if let Err(error) = repl() {
+                exit_with(error);
+            }
+        }
+        (Command::Debug, Some(filename)) => debug_file(filename),
+        (Command::Compile, Some(filename)) => compile_file(filename, &options),
+        (Command::Tokens, Some(filename)) => dump_tokens(filename),
+        (Command::Run, Some(filename)) if options.dump_ast => dump_ast(filename),
+        (Command::Run, Some(filename)) => match options.dump_bytecode {
+            Some(DumpFormat::Json) => dump_json(filename, &options),
+            None => run_file(filename, &options),
+        },
     }
 
     Ok(())
 }
+
+fn usage() -> ! {
+    println!("Usage: rlox [-O] [--dump-bytecode=json] [--trace | --profile] [file]");
+    println!("       rlox --ast <file>");
+    println!("       rlox debug <file>");
+    println!("       rlox compile [-O] <file> -o <output>");
+    println!("       rlox fmt [--check] <file>...");
+    println!("       rlox lint <file>...");
+    println!("       rlox lsp");
+    println!("       rlox tokens <file>");
+    std::process::exit(64);
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Options {
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" if options.command == Command::Compile => {
+                options.output = Some(args.next().unwrap_or_else(|| usage()))
+            }
+            "--dump-bytecode=json" => options.dump_bytecode = Some(DumpFormat::Json),
+            "--ast" => options.dump_ast = true,
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = true,
+            "-O" => options.optimize = true,
+            "--check" if options.command == Command::Fmt => options.check = true,
+            flag if flag.starts_with('-') => usage(),
+            _ if matches!(options.command, Command::Fmt | Command::Lint) => options.files.push(arg),
+            _ if options.filename.is_some() => usage(),
+            "debug" if options.command == Command::Run => options.command = Command::Debug,
+            "compile" if options.command == Command::Run => options.command = Command::Compile,
+            "fmt" if options.command == Command::Run => options.command = Command::Fmt,
+            "lint" if options.command == Command::Run => options.command = Command::Lint,
+            "lsp" if options.command == Command::Run => options.command = Command::Lsp,
+            "tokens" if options.command == Command::Run => options.command = Command::Tokens,
+            _ => options.filename = Some(arg),
+        }
+    }
+
+    let needs_file = matches!(
+        options.command,
+        Command::Debug | Command::Compile | Command::Tokens
+    ) || options.dump_bytecode.is_some()
+        || options.dump_ast;
+
+    let missing_files =
+        matches!(options.command, Command::Fmt | Command::Lint) && options.files.is_empty();
+
+    let missing_output = options.command == Command::Compile && options.output.is_none();
+
+    if (needs_file && options.filename.is_none())
+        || missing_output
+        || missing_files
+        || (options.trace && options.profile)
+    {
+        usage();
+    }
+
+    options
+}
+
+fn read_file(filename: &str) -> String {
+    match fs::read_to_string(filename) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Could not read file \"{}\": {}", filename, error);
+            std::process::exit(74);
+        }
+    }
+}
+
+/// Gets the chunk for a file, either by compiling its source or, for files
+/// compiled ahead of time, by loading the bytecode directly. The chunk goes
+/// through the peephole optimizer when `-O` is given.
+fn load_chunk(filename: &str, options: &Options) -> Chunk {
+    let chunk = load_unoptimized_chunk(filename);
+
+    if options.optimize {
+        optimize(&chunk)
+    } else {
+        chunk
+    }
+}
+
+fn load_unoptimized_chunk(filename: &str) -> Chunk {
+    if !filename.ends_with(".loxc") {
+        let code = read_file(filename);
+        let mut chunk = Chunk::new();
+
+        if let Err(error) = compile(&code, &mut chunk) {
+            exit_with(error);
+        }
+
+        return chunk;
+    }
+
+    let bytes = match fs::read(filename) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("Could not read file \"{}\": {}", filename, error);
+            std::process::exit(74);
+        }
+    };
+
+    match deserialize_chunk(&bytes) {
+        Ok(chunk) => chunk,
+        Err(error) => {
+            eprintln!("Could not load \"{}\": {}", filename, error);
+            std::process::exit(65);
+        }
+    }
+}
+
+/// Loads the file to run, along with the modules it imports when it's a
+/// source file, in the order they have to run.
+fn load_program(filename: &str, options: &Options) -> Vec<Rc<Chunk>> {
+    if filename.ends_with(".loxc") {
+        return vec![Rc::new(load_chunk(filename, options))];
+    }
+
+    match ModuleLoader::new().load(filename.as_ref()) {
+        Ok(chunks) if options.optimize => chunks
+            .iter()
+            .map(|chunk| Rc::new(optimize(chunk)))
+            .collect(),
+        Ok(chunks) => chunks,
+        Err(error) => {
+            eprintln!("{}", error);
+
+            match error {
+                ModuleError::Io { .. } => std::process::exit(74),
+                ModuleError::Compile { .. } => std::process::exit(65),
+            }
+        }
+    }
+}
+
+fn run_file(filename: &str, options: &Options) {
+    let chunks = load_program(filename, options);
+    let mut vm = Vm::new();
+
+    let profiler = Rc::new(RefCell::new(Profiler::new()));
+
+    if options.trace {
+        vm.set_hook(Box::new(StderrTracer));
+    } else if options.profile {
+        vm.set_hook(Box::new(Rc::clone(&profiler)));
+    }
+
+    let result = chunks
+        .iter()
+        .try_for_each(|chunk| vm.interpret(chunk).map(|_| ()));
+
+    if options.profile {
+        profiler
+            .borrow()
+            .report(&mut io::stderr())
+            .expect("Error writing profile");
+    }
+
+    if let Err(error) = result {
+        exit_with(error);
+    }
+}
+
+fn debug_file(filename: &str) {
+    let code = read_file(filename);
+    let mut vm = Vm::new();
+    vm.set_hook(Box::new(Debugger::new(
+        &code,
+        Box::new(io::BufReader::new(io::stdin())),
+        Box::new(io::stdout()),
+    )));
+
+    match run_script(&mut vm, &code) {
+        Ok(()) => {}
+        Err(error) => exit_with(error),
+    }
+}
+
+fn compile_file(filename: &str, options: &Options) {
+    let chunk = load_chunk(filename, options);
+    let output = options.output.as_ref().unwrap();
+
+    if let Err(error) = fs::write(output, serialize_chunk(&chunk)) {
+        eprintln!("Could not write file \"{}\": {}", output, error);
+        std::process::exit(74);
+    }
+}
+
+fn dump_json(filename: &str, options: &Options) {
+    let chunk = load_chunk(filename, options);
+    print!("{}", chunk_to_json(&chunk, "script"));
+}
+
+/// Lists every token in the file, trivia included, with the line and column
+/// it starts at.
+fn dump_tokens(filename: &str) {
+    let code = read_file(filename);
+    let mut scanner = Scanner::new();
+
+    for token in scanner.scan_with_trivia(&code) {
+        println!(
+            "{:>4}:{:<4} {:<12} {:?}",
+            token.start_line(),
+            token.column,
+            format!("{:?}", token.token_type),
+            token.code
+        );
+    }
+}
+
+fn dump_ast(filename: &str) {
+    let code = read_file(filename);
+
+    match parse(&code) {
+        Ok(program) => println!("{}", program),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(65);
+        }
+    }
+}
+
+/// Formats the files in place or, with `--check`, only reports the ones
+/// that aren't formatted and exits with 1 if there are any.
+fn format_files(filenames: &[String], check: bool) {
+    let mut had_error = false;
+    let mut unformatted = false;
+
+    for filename in filenames {
+        let code = read_file(filename);
+
+        let formatted = match format_code(&code) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                eprintln!("{}: {}", filename, error);
+                had_error = true;
+                continue;
+            }
+        };
+
+        if formatted == code {
+            continue;
+        }
+
+        if check {
+            eprintln!("{} is not formatted", filename);
+            unformatted = true;
+        } else if let Err(error) = fs::write(filename, formatted) {
+            eprintln!("Could not write file \"{}\": {}", filename, error);
+            std::process::exit(74);
+        }
+    }
+
+    if had_error {
+        std::process::exit(65);
+    } else if unformatted {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the lint diagnostics of the files. Exits with 65 if any of them
+/// doesn't compile, or with 1 if there are only warnings.
+fn lint_files(filenames: &[String]) {
+    let mut had_error = false;
+    let mut had_warning = false;
+
+    for filename in filenames {
+        for diagnostic in lint(&read_file(filename)) {
+            eprintln!("{}: {}", filename, diagnostic);
+
+            match diagnostic.severity {
+                Severity::Error => had_error = true,
+                Severity::Warning => had_warning = true,
+            }
+        }
+    }
+
+    if had_error {
+        std::process::exit(65);
+    } else if had_warning {
+        std::process::exit(1);
+    }
+}
+
+fn serve_lsp() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    match run_language_server(&mut stdin.lock(), &mut stdout.lock()) {
+        Ok(code) => std::process::exit(code),
+        Err(error) => {
+            eprintln!("Language server error: {}", error);
+            std::process::exit(74);
+        }
+    }
+}
+
+fn exit_with(error: InterpretError) -> ! {
+    match error {
+        InterpretError::CompileError => std::process::exit(65),
+        InterpretError::RuntimeError => std::process::exit(70),
+        InterpretError::Stopped => std::process::exit(0),
+        InterpretError::Exit(code) => {
+            let _ = io::stdout().flush();
+            std::process::exit(i32::from(code))
+        }
+    }
+}
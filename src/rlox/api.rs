@@ -1,2 +1,29 @@
+pub use crate::rlox::assembler::{assemble, AssembleError};
+pub use crate::rlox::ast::{BinaryOperator, Expr, Import, Literal, Program, UnaryOperator};
+pub use crate::rlox::bytecode::{deserialize_chunk, serialize_chunk, LoadError};
+pub use crate::rlox::chunk::Chunk;
+pub use crate::rlox::codegen::generate;
+pub use crate::rlox::compiler::compile;
+pub use crate::rlox::convert::{ConversionError, FromLox, FromLoxArgs, IntoLox};
+pub use crate::rlox::debugger::Debugger;
+pub use crate::rlox::diagnostic::{Diagnostic, Severity};
+pub use crate::rlox::disassembler::{
+    constant_literal, decode_instruction, disassemble, disassemble_instruction,
+    disassemble_to_string, instruction_records, InstructionRecord,
+};
+pub use crate::rlox::formatter::format_code;
+pub use crate::rlox::instruction::Instruction;
+pub use crate::rlox::json::{chunk_to_json, parse_json, Json};
+pub use crate::rlox::linter::lint;
+pub use crate::rlox::lsp::run_language_server;
+pub use crate::rlox::module::{ModuleError, ModuleLoader};
+pub use crate::rlox::optimizer::optimize;
+pub use crate::rlox::parser::parse;
+pub use crate::rlox::profiler::Profiler;
 pub use crate::rlox::repl::repl;
-pub use crate::rlox::vm::InterpretError;
+pub use crate::rlox::scanner::{Scanner, ScannerIterator};
+pub use crate::rlox::script::run_script;
+pub use crate::rlox::token::{Token, TokenType};
+pub use crate::rlox::trace::{StderrTracer, VmHook};
+pub use crate::rlox::value::{NativeFunction, Value};
+pub use crate::rlox::vm::{InterpretError, Vm};
@@ -0,0 +1,351 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::instruction::Instruction;
+use crate::rlox::value::Value;
+use std::fmt;
+
+/// An error in a `.loxasm` source, with the line of the source it's on.
+#[derive(Debug, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Builds a chunk out of its textual representation. The format is the one
+/// `disassemble` writes, so its output can be fed back as is:
+///
+/// ```text
+/// == script ==
+/// 0000    1 OpConstant         0 1.5
+/// 0001    | OpNegate
+/// 0002    2 OpReturn
+/// ```
+///
+/// Each instruction can be preceded by its offset and its line (`|` meaning
+/// the same line as the previous instruction), both optional when writing
+/// assembly by hand. When the line is missing, the previous one is used.
+/// `OpConstant`, `OpGetGlobal`, `OpGetProperty` and the instructions fused
+/// with `OpConstant` take the literal for their constant, optionally preceded
+/// by the index it should have in the constant pool. `OpCall`,
+/// `OpBuildList` and `OpBuildMap` take their count.
+/// Blank lines, `== name ==` headers and everything after a `;` are
+/// ignored.
+pub fn assemble(source: &str) -> Result<Chunk, AssembleError> {
+    let mut instructions = vec![];
+    let mut constants: Vec<Option<Value>> = vec![];
+    let mut current_line = 1;
+
+    for (index, text) in source.lines().enumerate() {
+        let source_line = index + 1;
+        let error = |message: String| AssembleError {
+            line: source_line,
+            message,
+        };
+
+        let text = strip_comment(text).trim();
+
+        if text.is_empty() || (text.starts_with("==") && text.ends_with("==")) {
+            continue;
+        }
+
+        let (prefix, name, rest) = split_instruction(text)
+            .ok_or_else(|| error(format!("Expected an instruction in '{}'", text)))?;
+
+        match prefix.as_slice() {
+            [] | ["|"] | [_, "|"] => {}
+            [line] | [_, line] => {
+                current_line = line
+                    .parse()
+                    .map_err(|_| error(format!("Invalid line number '{}'", line)))?;
+            }
+            _ => return Err(error(format!("Unexpected '{}'", prefix.join(" ")))),
+        }
+
+        let instruction = match name {
+            "OpConstant" | "OpGetGlobal" | "OpGetProperty" | "OpAddConstant"
+            | "OpSubtractConstant" | "OpMultiplyConstant" | "OpDivideConstant" => {
+                let (explicit_index, literal) = match rest.find(char::is_whitespace) {
+                    Some(split) if rest[..split].bytes().all(|b| b.is_ascii_digit()) => {
+                        (Some(&rest[..split]), rest[split..].trim())
+                    }
+                    _ => (None, rest),
+                };
+
+                let value = parse_literal(literal).map_err(error)?;
+
+                let constant_index = match explicit_index {
+                    Some(explicit_index) => {
+                        let constant_index: usize = explicit_index
+                            .parse()
+                            .map_err(|_| error(format!("Invalid index '{}'", explicit_index)))?;
+
+                        if constants.len() <= constant_index {
+                            constants.resize(constant_index + 1, None);
+                        }
+
+                        match &constants[constant_index] {
+                            Some(existing) if *existing != value => {
+                                return Err(error(format!(
+                                    "Constant {} was already defined with a different value",
+                                    constant_index
+                                )));
+                            }
+                            _ => constants[constant_index] = Some(value),
+                        }
+
+                        constant_index
+                    }
+                    None => {
+                        constants.push(Some(value));
+                        constants.len() - 1
+                    }
+                };
+
+                match name {
+                    "OpConstant" => Instruction::OpConstant(constant_index),
+                    "OpGetGlobal" => Instruction::OpGetGlobal(constant_index),
+                    "OpGetProperty" => Instruction::OpGetProperty(constant_index),
+                    "OpAddConstant" => Instruction::OpAddConstant(constant_index),
+                    "OpSubtractConstant" => Instruction::OpSubtractConstant(constant_index),
+                    "OpMultiplyConstant" => Instruction::OpMultiplyConstant(constant_index),
+                    _ => Instruction::OpDivideConstant(constant_index),
+                }
+            }
+            "OpCall" | "OpBuildList" | "OpBuildMap" => {
+                let count = rest
+                    .parse()
+                    .map_err(|_| error(format!("Invalid count '{}'", rest)))?;
+
+                match name {
+                    "OpCall" => Instruction::OpCall(count),
+                    "OpBuildList" => Instruction::OpBuildList(count),
+                    _ => Instruction::OpBuildMap(count),
+                }
+            }
+            _ if !rest.is_empty() => {
+                return Err(error(format!("{} doesn't take operands", name)));
+            }
+            "OpReturn" => Instruction::OpReturn,
+            "OpNegate" => Instruction::OpNegate,
+            "OpAdd" => Instruction::OpAdd,
+            "OpSubtract" => Instruction::OpSubtract,
+            "OpMultiply" => Instruction::OpMultiply,
+            "OpDivide" => Instruction::OpDivide,
+            "OpNil" => Instruction::OpNil,
+            "OpTrue" => Instruction::OpTrue,
+            "OpFalse" => Instruction::OpFalse,
+            "OpNot" => Instruction::OpNot,
+            "OpEqual" => Instruction::OpEqual,
+            "OpNotEqual" => Instruction::OpNotEqual,
+            "OpGreater" => Instruction::OpGreater,
+            "OpGreaterEqual" => Instruction::OpGreaterEqual,
+            "OpLess" => Instruction::OpLess,
+            "OpLessEqual" => Instruction::OpLessEqual,
+            "OpGetIndex" => Instruction::OpGetIndex,
+            "OpSetIndex" => Instruction::OpSetIndex,
+            "OpSlice" => Instruction::OpSlice,
+            _ => return Err(error(format!("Unknown instruction '{}'", name))),
+        };
+
+        instructions.push((instruction, current_line));
+    }
+
+    let mut chunk = Chunk::new();
+
+    for (index, constant) in constants.into_iter().enumerate() {
+        match constant {
+            Some(constant) => chunk.add_constant(constant),
+            None => {
+                return Err(AssembleError {
+                    line: source.lines().count(),
+                    message: format!("Constant {} is never defined", index),
+                })
+            }
+        };
+    }
+
+    for (instruction, line) in instructions {
+        chunk.add_instruction(instruction, line);
+    }
+
+    Ok(chunk)
+}
+
+/// Removes a `;` comment, taking care not to cut a string literal short.
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..index],
+            _ => {}
+        }
+    }
+
+    text
+}
+
+/// Splits an instruction line into whatever comes before the opcode name
+/// (offset and line), the name itself and its operands.
+fn split_instruction(text: &str) -> Option<(Vec<&str>, &str, &str)> {
+    let mut prefix = vec![];
+    let mut rest = text;
+
+    loop {
+        let (word, remaining) = match rest.find(char::is_whitespace) {
+            Some(split) => (&rest[..split], rest[split..].trim_start()),
+            None => (rest, ""),
+        };
+
+        if word.is_empty() {
+            return None;
+        }
+
+        if word.starts_with("Op") {
+            return Some((prefix, word, remaining));
+        }
+
+        prefix.push(word);
+        rest = remaining;
+    }
+}
+
+fn parse_literal(literal: &str) -> Result<Value, String> {
+    match literal {
+        "nil" => return Ok(Value::Nil),
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+
+    if literal.len() >= 2 && literal.starts_with('"') && literal.ends_with('"') {
+        let mut string = String::new();
+        let mut chars = literal[1..literal.len() - 1].chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                string.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some('n') => string.push('\n'),
+                Some('r') => string.push('\r'),
+                Some('t') => string.push('\t'),
+                _ => return Err(format!("Invalid escape in {}", literal)),
+            }
+        }
+
+        return Ok(Value::from(string));
+    }
+
+    literal
+        .parse::<f64>()
+        .map(Value::Number)
+        .map_err(|_| format!("Invalid constant '{}'", literal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::compiler::compile;
+    use crate::rlox::disassembler::disassemble_to_string;
+
+    #[test]
+    fn test_round_trip_compiled_code() {
+        for code in &[
+            "1 + 2 * 3",
+            "-(1.5 / 3) == 4",
+            "\"a\" + \"b\"",
+            "!nil",
+            "1 +\n2\n>\n3",
+            "max(1, -2) * sqrt(4)",
+            "[1, [\"a\"], []][-1].len()",
+            "{\"a\": 1, 2: {}}[\"a\"]",
+            "\"héllo\"[1:-1] + [1, 2][:1][0]",
+        ] {
+            let mut chunk = Chunk::new();
+            compile(code, &mut chunk).expect("Error compiling code");
+
+            let assembly = disassemble_to_string(&chunk, "code");
+            assert_eq!(assemble(&assembly), Ok(chunk));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_special_constants() {
+        let mut chunk = Chunk::new();
+
+        for value in [
+            Value::from("say \"hi\"; \\ bye\n"),
+            Value::Number(f64::INFINITY),
+            Value::Number(-0.0),
+            Value::Number(0.1),
+            Value::Boolean(false),
+            Value::Nil,
+        ] {
+            let index = chunk.add_constant(value);
+            chunk.add_instruction(Instruction::OpConstant(index), 1);
+        }
+        chunk.add_instruction(Instruction::OpReturn, 1);
+
+        let assembly = disassemble_to_string(&chunk, "constants");
+        assert_eq!(assemble(&assembly), Ok(chunk));
+    }
+
+    #[test]
+    fn test_hand_written_assembly() {
+        let chunk = assemble(
+            "; 2 + 3 and a comparison
+             OpConstant 2
+             OpConstant 3
+             OpAdd
+             2 OpNil
+             OpEqual
+             OpReturn",
+        )
+        .expect("Error assembling code");
+
+        let mut expected = Chunk::new();
+        let two = expected.add_constant(Value::Number(2.0));
+        let three = expected.add_constant(Value::Number(3.0));
+        expected.add_instruction(Instruction::OpConstant(two), 1);
+        expected.add_instruction(Instruction::OpConstant(three), 1);
+        expected.add_instruction(Instruction::OpAdd, 1);
+        expected.add_instruction(Instruction::OpNil, 2);
+        expected.add_instruction(Instruction::OpEqual, 2);
+        expected.add_instruction(Instruction::OpReturn, 2);
+
+        assert_eq!(chunk, expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble("OpAdd\nOpJump").unwrap_err(),
+            AssembleError {
+                line: 2,
+                message: String::from("Unknown instruction 'OpJump'"),
+            }
+        );
+        assert!(assemble("OpAdd 1").is_err());
+        assert!(assemble("OpConstant \"unterminated").is_err());
+        assert!(assemble("OpConstant 1 2\nOpConstant 1 3").is_err());
+        assert!(assemble("OpConstant 1 2").is_err());
+        assert!(assemble("OpCall").is_err());
+        assert!(assemble("OpCall -1").is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use crate::rlox::disassembler::constant_literal;
+use crate::rlox::instruction::Instruction;
+use crate::rlox::token::TokenType;
+use crate::rlox::value::Value;
+use std::fmt;
+
+/// A whole file: the modules it imports, followed by its code.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    pub imports: Vec<Import>,
+    pub expression: Expr,
+}
+
+/// Prints each import as `(import "path")` on a line of its own, followed
+/// by the expression.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for import in &self.imports {
+            writeln!(
+                f,
+                "(import {})",
+                constant_literal(&Value::from(import.path.as_str()))
+            )?;
+        }
+
+        write!(f, "{}", self.expression)
+    }
+}
+
+/// An `import "path";` declaration, at the position of the `import` keyword.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// An expression as written in the source. Each node keeps the position of
+/// the token it's built around: the literal or name itself, the opening
+/// parenthesis or bracket, or the operator.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Literal {
+        value: Literal,
+        line: usize,
+        column: usize,
+    },
+    Grouping {
+        expression: Box<Expr>,
+        line: usize,
+        column: usize,
+        /// The line of the closing parenthesis.
+        end_line: usize,
+    },
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expr>,
+        line: usize,
+        column: usize,
+    },
+    Binary {
+        left: Box<Expr>,
+        operator: BinaryOperator,
+        right: Box<Expr>,
+        line: usize,
+        column: usize,
+    },
+    Variable {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+        line: usize,
+        column: usize,
+        /// The line of the closing parenthesis.
+        end_line: usize,
+    },
+    List {
+        elements: Vec<Expr>,
+        line: usize,
+        column: usize,
+        /// The line of the closing bracket.
+        end_line: usize,
+    },
+    Map {
+        entries: Vec<(Expr, Expr)>,
+        line: usize,
+        column: usize,
+        /// The line of the closing brace.
+        end_line: usize,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        line: usize,
+        column: usize,
+        /// The line of the closing bracket.
+        end_line: usize,
+    },
+    /// The bounds are `nil` literals when they're left out.
+    Slice {
+        object: Box<Expr>,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        line: usize,
+        column: usize,
+        /// The line of the closing bracket.
+        end_line: usize,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+        line: usize,
+        column: usize,
+    },
+    /// A method looked up on a value, positioned at the method's name.
+    Get {
+        object: Box<Expr>,
+        name: String,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl Expr {
+    /// The line and column of the token the expression is built around.
+    pub fn position(&self) -> (usize, usize) {
+        match self {
+            Expr::Literal { line, column, .. }
+            | Expr::Grouping { line, column, .. }
+            | Expr::Unary { line, column, .. }
+            | Expr::Binary { line, column, .. }
+            | Expr::Variable { line, column, .. }
+            | Expr::Call { line, column, .. }
+            | Expr::List { line, column, .. }
+            | Expr::Map { line, column, .. }
+            | Expr::Index { line, column, .. }
+            | Expr::Slice { line, column, .. }
+            | Expr::SetIndex { line, column, .. }
+            | Expr::Get { line, column, .. } => (*line, *column),
+        }
+    }
+
+    /// The line of the last token of the expression.
+    pub fn end_line(&self) -> usize {
+        match self {
+            Expr::Literal { line, .. } | Expr::Variable { line, .. } | Expr::Get { line, .. } => {
+                *line
+            }
+            Expr::Grouping { end_line, .. }
+            | Expr::Call { end_line, .. }
+            | Expr::List { end_line, .. }
+            | Expr::Map { end_line, .. }
+            | Expr::Index { end_line, .. }
+            | Expr::Slice { end_line, .. } => *end_line,
+            Expr::SetIndex { value, .. } => value.end_line(),
+            Expr::Unary { operand, .. } => operand.end_line(),
+            Expr::Binary { right, .. } => right.end_line(),
+        }
+    }
+}
+
+/// Prints the expression as an S-expression, e.g. `(+ 1 (group (- 2)))`,
+/// `(call max 1 2)`, `(index (list 1 2) 0)` or `(map "a" 1 "b" 2)`.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Literal { value, .. } => write!(f, "{}", value),
+            Expr::Grouping { expression, .. } => write!(f, "(group {})", expression),
+            Expr::Unary {
+                operator, operand, ..
+            } => write!(f, "({} {})", operator.symbol(), operand),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => write!(f, "({} {} {})", operator.symbol(), left, right),
+            Expr::Variable { name, .. } => write!(f, "{}", name),
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                write!(f, "(call {}", callee)?;
+                write_list(f, arguments)
+            }
+            Expr::List { elements, .. } => {
+                write!(f, "(list")?;
+                write_list(f, elements)
+            }
+            Expr::Map { entries, .. } => {
+                write!(f, "(map")?;
+
+                for (key, value) in entries {
+                    write!(f, " {} {}", key, value)?;
+                }
+
+                write!(f, ")")
+            }
+            Expr::Index { object, index, .. } => write!(f, "(index {} {})", object, index),
+            Expr::Slice {
+                object, start, end, ..
+            } => write!(f, "(slice {} {} {})", object, start, end),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => write!(f, "(set-index {} {} {})", object, index, value),
+            Expr::Get { object, name, .. } => write!(f, "(get {} {})", object, name),
+        }
+    }
+}
+
+/// Writes each expression after a space, then closes the parenthesis.
+fn write_list(f: &mut fmt::Formatter, expressions: &[Expr]) -> fmt::Result {
+    for expression in expressions {
+        write!(f, " {}", expression)?;
+    }
+
+    write!(f, ")")
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Literal {
+    Number(f64),
+    String(String),
+    True,
+    False,
+    Nil,
+}
+
+impl Literal {
+    pub fn to_value(&self) -> Value {
+        match self {
+            Literal::Number(number) => Value::Number(*number),
+            Literal::String(string) => Value::from(string.as_str()),
+            Literal::True => Value::Boolean(true),
+            Literal::False => Value::Boolean(false),
+            Literal::Nil => Value::Nil,
+        }
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", constant_literal(&self.to_value()))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+impl UnaryOperator {
+    pub fn from_token_type(token_type: TokenType) -> Option<UnaryOperator> {
+        match token_type {
+            TokenType::Minus => Some(UnaryOperator::Negate),
+            TokenType::Bang => Some(UnaryOperator::Not),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOperator::Negate => "-",
+            UnaryOperator::Not => "!",
+        }
+    }
+
+    pub fn instruction(&self) -> Instruction {
+        match self {
+            UnaryOperator::Negate => Instruction::OpNegate,
+            UnaryOperator::Not => Instruction::OpNot,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl BinaryOperator {
+    pub fn from_token_type(token_type: TokenType) -> Option<BinaryOperator> {
+        match token_type {
+            TokenType::Plus => Some(BinaryOperator::Add),
+            TokenType::Minus => Some(BinaryOperator::Subtract),
+            TokenType::Star => Some(BinaryOperator::Multiply),
+            TokenType::Slash => Some(BinaryOperator::Divide),
+            TokenType::EqualEqual => Some(BinaryOperator::Equal),
+            TokenType::BangEqual => Some(BinaryOperator::NotEqual),
+            TokenType::Greater => Some(BinaryOperator::Greater),
+            TokenType::GreaterEqual => Some(BinaryOperator::GreaterEqual),
+            TokenType::Less => Some(BinaryOperator::Less),
+            TokenType::LessEqual => Some(BinaryOperator::LessEqual),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+        }
+    }
+
+    pub fn instruction(&self) -> Instruction {
+        match self {
+            BinaryOperator::Add => Instruction::OpAdd,
+            BinaryOperator::Subtract => Instruction::OpSubtract,
+            BinaryOperator::Multiply => Instruction::OpMultiply,
+            BinaryOperator::Divide => Instruction::OpDivide,
+            BinaryOperator::Equal => Instruction::OpEqual,
+            BinaryOperator::NotEqual => Instruction::OpNotEqual,
+            BinaryOperator::Greater => Instruction::OpGreater,
+            BinaryOperator::GreaterEqual => Instruction::OpGreaterEqual,
+            BinaryOperator::Less => Instruction::OpLess,
+            BinaryOperator::LessEqual => Instruction::OpLessEqual,
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::instruction::Instruction;
+use crate::rlox::value::{Obj, ObjValue, Value};
+use std::convert::TryFrom;
+use std::fmt;
+
+// A compiled file starts with the magic bytes, followed by the format
+// version. Everything after that is the chunk itself:
+//
+//   u32 constant count, then each constant as a tag byte and its payload
+//   u32 instruction count, then each instruction as an opcode byte, its
+//       operands (a u32 constant index, a u8 argument count or a u32 element
+//       or entry count) and the u32 line it came from
+//
+// All integers are little endian. The version has to be bumped whenever the
+// layout or the opcode numbering changes.
+const MAGIC: &[u8; 4] = b"LOXC";
+const VERSION: u16 = 1;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidConstantTag(u8),
+    InvalidString,
+    InvalidOpcode(u8),
+    InvalidConstantIndex(usize),
+    /// A global or property name that isn't a string constant.
+    InvalidNameConstant(usize),
+    /// The instruction at the offset pops more values than the stack holds.
+    StackUnderflow(usize),
+    TrailingData,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "Not a compiled Lox file"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported bytecode version {} (expected {})",
+                version, VERSION
+            ),
+            LoadError::Truncated => write!(f, "File is truncated"),
+            LoadError::InvalidConstantTag(tag) => write!(f, "Invalid constant tag {}", tag),
+            LoadError::InvalidString => write!(f, "Invalid UTF-8 in string constant"),
+            LoadError::InvalidOpcode(opcode) => write!(f, "Invalid opcode {}", opcode),
+            LoadError::InvalidConstantIndex(index) => {
+                write!(f, "Instruction refers to missing constant {}", index)
+            }
+            LoadError::InvalidNameConstant(index) => {
+                write!(f, "Name refers to constant {}, which isn't a string", index)
+            }
+            LoadError::StackUnderflow(offset) => {
+                write!(
+                    f,
+                    "Instruction {} pops more values than the stack holds",
+                    offset
+                )
+            }
+            LoadError::TrailingData => write!(f, "Unexpected data after the end of the chunk"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+pub fn serialize_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut out = vec![];
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+
+    let constants: Vec<&Value> = chunk.constants().collect();
+    write_u32(&mut out, constants.len());
+
+    for constant in constants {
+        match constant {
+            Value::Nil => out.push(TAG_NIL),
+            Value::Boolean(false) => out.push(TAG_FALSE),
+            Value::Boolean(true) => out.push(TAG_TRUE),
+            Value::Number(number) => {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&number.to_le_bytes());
+            }
+            Value::Obj(obj) => match &obj.value {
+                ObjValue::String(string) => {
+                    out.push(TAG_STRING);
+                    write_u32(&mut out, string.len());
+                    out.extend_from_slice(string.as_bytes());
+                }
+                _ => panic!(
+                    "Only literals can be serialized, found a {}",
+                    constant.type_name()
+                ),
+            },
+        }
+    }
+
+    write_u32(&mut out, chunk.instructions_count());
+
+    for (index, instruction) in chunk.instructions().enumerate() {
+        out.push(opcode(instruction));
+
+        if let Some(constant_index) = instruction.constant_operand() {
+            write_u32(&mut out, constant_index);
+        }
+
+        match instruction {
+            Instruction::OpCall(arguments_count) => {
+                let arguments_count =
+                    u8::try_from(*arguments_count).expect("Too many arguments to be serialized");
+                out.push(arguments_count);
+            }
+            Instruction::OpBuildList(elements_count) => write_u32(&mut out, *elements_count),
+            Instruction::OpBuildMap(entries_count) => write_u32(&mut out, *entries_count),
+            _ => {}
+        }
+
+        write_u32(&mut out, *chunk.line_at(index));
+    }
+
+    out
+}
+
+pub fn deserialize_chunk(bytes: &[u8]) -> Result<Chunk, LoadError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len()).map_err(|_| LoadError::BadMagic)? != MAGIC {
+        return Err(LoadError::BadMagic);
+    }
+
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+
+    let mut chunk = Chunk::new();
+
+    let constants_count = reader.u32()?;
+    for _ in 0..constants_count {
+        let constant = match reader.u8()? {
+            TAG_NIL => Value::Nil,
+            TAG_FALSE => Value::Boolean(false),
+            TAG_TRUE => Value::Boolean(true),
+            TAG_NUMBER => Value::Number(f64::from_le_bytes(reader.array()?)),
+            TAG_STRING => {
+                let length = reader.u32()?;
+                let bytes = reader.take(length)?;
+                let string = std::str::from_utf8(bytes).map_err(|_| LoadError::InvalidString)?;
+                Value::from(string)
+            }
+            tag => return Err(LoadError::InvalidConstantTag(tag)),
+        };
+
+        chunk.add_constant(constant);
+    }
+
+    let instructions_count = reader.u32()?;
+    for _ in 0..instructions_count {
+        let instruction = match reader.u8()? {
+            0 => Instruction::OpReturn,
+            opcode @ 1 | opcode @ 17..=21 | opcode @ 26 => {
+                let index = reader.u32()?;
+
+                if index >= constants_count {
+                    return Err(LoadError::InvalidConstantIndex(index));
+                }
+
+                let is_name = opcode == 21 || opcode == 26;
+                let is_string = matches!(
+                    chunk.constant_at(index),
+                    Value::Obj(Obj {
+                        value: ObjValue::String(_)
+                    })
+                );
+
+                if is_name && !is_string {
+                    return Err(LoadError::InvalidNameConstant(index));
+                }
+
+                match opcode {
+                    1 => Instruction::OpConstant(index),
+                    17 => Instruction::OpAddConstant(index),
+                    18 => Instruction::OpSubtractConstant(index),
+                    19 => Instruction::OpMultiplyConstant(index),
+                    20 => Instruction::OpDivideConstant(index),
+                    21 => Instruction::OpGetGlobal(index),
+                    _ => Instruction::OpGetProperty(index),
+                }
+            }
+            2 => Instruction::OpNegate,
+            3 => Instruction::OpAdd,
+            4 => Instruction::OpSubtract,
+            5 => Instruction::OpMultiply,
+            6 => Instruction::OpDivide,
+            7 => Instruction::OpNil,
+            8 => Instruction::OpTrue,
+            9 => Instruction::OpFalse,
+            10 => Instruction::OpNot,
+            11 => Instruction::OpEqual,
+            12 => Instruction::OpGreater,
+            13 => Instruction::OpLess,
+            14 => Instruction::OpNotEqual,
+            15 => Instruction::OpGreaterEqual,
+            16 => Instruction::OpLessEqual,
+            22 => Instruction::OpCall(reader.u8()? as usize),
+            23 => Instruction::OpBuildList(reader.u32()?),
+            24 => Instruction::OpGetIndex,
+            25 => Instruction::OpSetIndex,
+            27 => Instruction::OpBuildMap(reader.u32()?),
+            28 => Instruction::OpSlice,
+            opcode => return Err(LoadError::InvalidOpcode(opcode)),
+        };
+
+        let line = reader.u32()?;
+        chunk.add_instruction(instruction, line);
+    }
+
+    if reader.position != bytes.len() {
+        return Err(LoadError::TrailingData);
+    }
+
+    check_stack(&chunk)?;
+    Ok(chunk)
+}
+
+// The VM trusts the compiler to never pop an empty stack, so a loaded chunk
+// has to be checked for it. There are no jumps, so following the instructions
+// in order gives the exact stack depth at each of them.
+fn check_stack(chunk: &Chunk) -> Result<(), LoadError> {
+    let mut depth: usize = 0;
+
+    for (offset, instruction) in chunk.instructions().enumerate() {
+        let (popped, pushed) = instruction.stack_effect();
+        depth = depth
+            .checked_sub(popped)
+            .ok_or(LoadError::StackUnderflow(offset))?
+            + pushed;
+    }
+
+    Ok(())
+}
+
+fn opcode(instruction: &Instruction) -> u8 {
+    match instruction {
+        Instruction::OpReturn => 0,
+        Instruction::OpConstant(_) => 1,
+        Instruction::OpNegate => 2,
+        Instruction::OpAdd => 3,
+        Instruction::OpSubtract => 4,
+        Instruction::OpMultiply => 5,
+        Instruction::OpDivide => 6,
+        Instruction::OpNil => 7,
+        Instruction::OpTrue => 8,
+        Instruction::OpFalse => 9,
+        Instruction::OpNot => 10,
+        Instruction::OpEqual => 11,
+        Instruction::OpGreater => 12,
+        Instruction::OpLess => 13,
+        Instruction::OpNotEqual => 14,
+        Instruction::OpGreaterEqual => 15,
+        Instruction::OpLessEqual => 16,
+        Instruction::OpAddConstant(_) => 17,
+        Instruction::OpSubtractConstant(_) => 18,
+        Instruction::OpMultiplyConstant(_) => 19,
+        Instruction::OpDivideConstant(_) => 20,
+        Instruction::OpGetGlobal(_) => 21,
+        Instruction::OpCall(_) => 22,
+        Instruction::OpBuildList(_) => 23,
+        Instruction::OpGetIndex => 24,
+        Instruction::OpSetIndex => 25,
+        Instruction::OpGetProperty(_) => 26,
+        Instruction::OpBuildMap(_) => 27,
+        Instruction::OpSlice => 28,
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("Chunk is too big to be serialized");
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], LoadError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(LoadError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_chunk() -> Chunk {
+        let mut chunk = Chunk::new();
+        let number = chunk.add_constant(Value::Number(1.5));
+        let string = chunk.add_constant(Value::from("héllo"));
+        chunk.add_constant(Value::Nil);
+        chunk.add_constant(Value::Boolean(true));
+        chunk.add_instruction(Instruction::OpConstant(number), 1);
+        chunk.add_instruction(Instruction::OpNegate, 1);
+        chunk.add_instruction(Instruction::OpConstant(string), 2);
+        chunk.add_instruction(Instruction::OpEqual, 2);
+        let name = chunk.add_constant(Value::from("clock"));
+        chunk.add_instruction(Instruction::OpGetGlobal(name), 3);
+        chunk.add_instruction(Instruction::OpCall(0), 3);
+        chunk.add_instruction(Instruction::OpBuildList(2), 3);
+        let name = chunk.add_constant(Value::from("len"));
+        chunk.add_instruction(Instruction::OpGetProperty(name), 3);
+        chunk.add_instruction(Instruction::OpNil, 3);
+        chunk.add_instruction(Instruction::OpGetIndex, 3);
+        chunk.add_instruction(Instruction::OpNil, 3);
+        chunk.add_instruction(Instruction::OpTrue, 3);
+        chunk.add_instruction(Instruction::OpSetIndex, 3);
+        chunk.add_instruction(Instruction::OpFalse, 3);
+        chunk.add_instruction(Instruction::OpBuildMap(1), 3);
+        chunk.add_instruction(Instruction::OpNil, 3);
+        chunk.add_instruction(Instruction::OpNil, 3);
+        chunk.add_instruction(Instruction::OpSlice, 3);
+        chunk.add_instruction(Instruction::OpReturn, 3);
+        chunk
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = sample_chunk();
+        let bytes = serialize_chunk(&chunk);
+        assert_eq!(deserialize_chunk(&bytes), Ok(chunk));
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert_eq!(deserialize_chunk(b"1 + 2"), Err(LoadError::BadMagic));
+        assert_eq!(deserialize_chunk(b"LO"), Err(LoadError::BadMagic));
+
+        let mut bytes = serialize_chunk(&sample_chunk());
+        bytes[4] = 99;
+        assert_eq!(
+            deserialize_chunk(&bytes),
+            Err(LoadError::UnsupportedVersion(99))
+        );
+    }
+
+    #[test]
+    fn test_truncated_files_are_rejected() {
+        let bytes = serialize_chunk(&sample_chunk());
+
+        for length in MAGIC.len()..bytes.len() {
+            assert_eq!(
+                deserialize_chunk(&bytes[..length]),
+                Err(LoadError::Truncated)
+            );
+        }
+    }
+
+    #[test]
+    fn test_corrupt_files_are_rejected() {
+        let mut chunk = Chunk::new();
+        chunk.add_instruction(Instruction::OpConstant(3), 1);
+        let bytes = serialize_chunk(&chunk);
+        assert_eq!(
+            deserialize_chunk(&bytes),
+            Err(LoadError::InvalidConstantIndex(3))
+        );
+
+        let mut bytes = serialize_chunk(&sample_chunk());
+        bytes.push(0);
+        assert_eq!(deserialize_chunk(&bytes), Err(LoadError::TrailingData));
+    }
+
+    fn load(instructions: Vec<Instruction>) -> Result<Chunk, LoadError> {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Number(1.0));
+        chunk.add_constant(Value::from("clock"));
+
+        for instruction in instructions {
+            chunk.add_instruction(instruction, 1);
+        }
+
+        deserialize_chunk(&serialize_chunk(&chunk))
+    }
+
+    #[test]
+    fn test_names_must_be_strings() {
+        use Instruction::*;
+
+        assert!(load(vec![OpGetGlobal(1), OpReturn]).is_ok());
+        assert_eq!(
+            load(vec![OpGetGlobal(0), OpReturn]),
+            Err(LoadError::InvalidNameConstant(0))
+        );
+        assert_eq!(
+            load(vec![OpNil, OpGetProperty(0), OpReturn]),
+            Err(LoadError::InvalidNameConstant(0))
+        );
+    }
+
+    #[test]
+    fn test_stack_underflow_is_rejected() {
+        use Instruction::*;
+
+        let underflows = vec![
+            (vec![OpAdd, OpReturn], 0),
+            (vec![OpReturn], 0),
+            (vec![OpNil, OpNegate, OpReturn, OpReturn], 3),
+            (vec![OpConstant(0), OpAddConstant(0), OpEqual, OpReturn], 2),
+            (vec![OpGetGlobal(1), OpNil, OpCall(5), OpReturn], 2),
+            (vec![OpNil, OpBuildList(2), OpReturn], 1),
+            (vec![OpNil, OpNil, OpNil, OpBuildMap(2), OpReturn], 3),
+            (vec![OpNil, OpNil, OpGetIndex, OpGetIndex, OpReturn], 3),
+            (vec![OpNil, OpNil, OpSetIndex, OpReturn], 2),
+            (vec![OpNil, OpNil, OpSlice, OpReturn], 2),
+            (vec![OpGetProperty(1), OpReturn], 0),
+        ];
+
+        for (instructions, offset) in underflows {
+            assert_eq!(load(instructions), Err(LoadError::StackUnderflow(offset)));
+        }
+
+        assert!(load(vec![OpGetGlobal(1), OpNil, OpNil, OpCall(2), OpReturn]).is_ok());
+        assert!(load(vec![OpNil, OpNil, OpBuildMap(1), OpReturn]).is_ok());
+    }
+}
@@ -1,6 +1,7 @@
 use super::instruction::Instruction;
 use super::value::Value;
 
+#[derive(Debug, Default, PartialEq)]
 pub struct Chunk {
     instructions: Vec<Instruction>,
     constants: Vec<Value>,
@@ -33,11 +34,29 @@ impl Chunk {
         self.lines.push(line);
     }
 
+    /// Removes the last instruction, e.g. to replace it with a better one.
+    pub fn pop_instruction(&mut self) -> Option<Instruction> {
+        self.lines.pop();
+        self.instructions.pop()
+    }
+
     pub fn add_constant(&mut self, constant: Value) -> usize {
         self.constants.push(constant);
         self.constants.len() - 1
     }
 
+    pub fn constants(&self) -> impl Iterator<Item = &Value> {
+        self.constants.iter()
+    }
+
+    pub fn constants_count(&self) -> usize {
+        self.constants.len()
+    }
+
+    pub fn pop_constant(&mut self) -> Option<Value> {
+        self.constants.pop()
+    }
+
     pub fn constant_at(&self, index: usize) -> &Value {
         self.constants
             .get(index)
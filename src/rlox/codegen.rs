@@ -0,0 +1,148 @@
+use crate::rlox::ast::Expr;
+use crate::rlox::chunk::Chunk;
+use crate::rlox::compiler::{emit_value, fold_binary, fold_operands, fold_unary};
+use crate::rlox::instruction::Instruction;
+use crate::rlox::value::Value;
+
+/// Generates the bytecode for an AST. The chunk is the same one `compile`
+/// produces for the code the AST was parsed from, constant folding included.
+pub fn generate(expression: &Expr, chunk: &mut Chunk) {
+    generate_expression(expression, chunk);
+    chunk.add_instruction(Instruction::OpReturn, expression.end_line());
+}
+
+// Instructions get the line of the last token of their expression, which is
+// the token the single-pass compiler has just consumed when it emits them.
+fn generate_expression(expression: &Expr, chunk: &mut Chunk) {
+    let line = expression.end_line();
+
+    match expression {
+        Expr::Literal { value, .. } => emit_value(chunk, value.to_value(), line),
+        Expr::Grouping { expression, .. } => generate_expression(expression, chunk),
+        Expr::Unary {
+            operator, operand, ..
+        } => {
+            let instruction = operator.instruction();
+            let start = chunk.instructions_count();
+            generate_expression(operand, chunk);
+
+            if !fold_operands(chunk, start, line, 1, |operands| {
+                fold_unary(&instruction, &operands[0])
+            }) {
+                chunk.add_instruction(instruction, line);
+            }
+        }
+        Expr::Binary {
+            left,
+            operator,
+            right,
+            ..
+        } => {
+            let instruction = operator.instruction();
+            let start = chunk.instructions_count();
+            generate_expression(left, chunk);
+            generate_expression(right, chunk);
+
+            if !fold_operands(chunk, start, line, 2, |operands| {
+                fold_binary(&instruction, &operands[0], &operands[1])
+            }) {
+                chunk.add_instruction(instruction, line);
+            }
+        }
+        Expr::Variable { name, .. } => {
+            let constant_index = chunk.add_constant(Value::from(name.as_str()));
+            chunk.add_instruction(Instruction::OpGetGlobal(constant_index), line);
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            generate_expression(callee, chunk);
+
+            for argument in arguments {
+                generate_expression(argument, chunk);
+            }
+
+            chunk.add_instruction(Instruction::OpCall(arguments.len()), line);
+        }
+        Expr::List { elements, .. } => {
+            for element in elements {
+                generate_expression(element, chunk);
+            }
+
+            chunk.add_instruction(Instruction::OpBuildList(elements.len()), line);
+        }
+        Expr::Map { entries, .. } => {
+            for (key, value) in entries {
+                generate_expression(key, chunk);
+                generate_expression(value, chunk);
+            }
+
+            chunk.add_instruction(Instruction::OpBuildMap(entries.len()), line);
+        }
+        Expr::Index { object, index, .. } => {
+            generate_expression(object, chunk);
+            generate_expression(index, chunk);
+            chunk.add_instruction(Instruction::OpGetIndex, line);
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            generate_expression(object, chunk);
+            generate_expression(start, chunk);
+            generate_expression(end, chunk);
+            chunk.add_instruction(Instruction::OpSlice, line);
+        }
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            generate_expression(object, chunk);
+            generate_expression(index, chunk);
+            generate_expression(value, chunk);
+            chunk.add_instruction(Instruction::OpSetIndex, line);
+        }
+        Expr::Get { object, name, .. } => {
+            generate_expression(object, chunk);
+            let constant_index = chunk.add_constant(Value::from(name.as_str()));
+            chunk.add_instruction(Instruction::OpGetProperty(constant_index), line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::compiler::compile;
+    use crate::rlox::parser::parse;
+
+    #[test]
+    fn test_matches_the_compiler() {
+        for code in &[
+            "1",
+            "1 + 2 * 3",
+            "(1 + 2) * -3 >= 4 == !nil",
+            "\"a\" + \"b\" != \"ab\"",
+            "\"a\" + 1 + 2",
+            "-\"a\" + (1 +\n2)",
+            "1 +\n\"a\"\n* (2\n)",
+            "!!(0 / 0 == 0 / 0)",
+            "1 < \"a\" == (true\n)",
+            "-sqrt(1 + 2,\n x\n)(\"a\" + 1) * f()",
+            "[1 + 2,\n [\n]][-1\n] = [\"a\"]\n.\nlen(\n)",
+            "-[1][0\n] + x.y",
+            "{\"a\"\n: 1 + 2, {}: [\n]}[nil\n] = {\n}",
+            "s[1\n:\n2][\n:\n][\n:-1\n] + s[0]",
+        ] {
+            let mut expected = Chunk::new();
+            compile(code, &mut expected).expect("Error compiling code");
+
+            let mut chunk = Chunk::new();
+            let program = parse(code).expect("Error parsing code");
+            generate(&program.expression, &mut chunk);
+
+            assert_eq!(chunk, expected, "for {:?}", code);
+        }
+    }
+}
@@ -1,20 +1,44 @@
 use crate::rlox::chunk::Chunk;
-use crate::rlox::disassembler::disassemble;
+use crate::rlox::diagnostic::Diagnostic;
 use crate::rlox::instruction::Instruction;
 use crate::rlox::scanner::{Scanner, ScannerIterator};
 use crate::rlox::token::{Token, TokenType};
-use crate::rlox::value::Value;
+use crate::rlox::value::{ObjValue, Value};
 use crate::rlox::vm::InterpretError;
 
+/// The most arguments a call can have, so that the count fits in a byte.
+pub(crate) const MAX_ARGUMENTS: usize = 255;
+
+/// Compiles the code into the chunk, printing any errors to stderr.
 pub fn compile(code: &str, chunk: &mut Chunk) -> Result<(), InterpretError> {
+    compile_with_diagnostics(code, chunk).map_err(|diagnostics| {
+        for diagnostic in diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+
+        InterpretError::CompileError
+    })
+}
+
+/// Like `compile`, but returns the errors for the caller to report.
+pub(crate) fn compile_with_diagnostics(
+    code: &str,
+    chunk: &mut Chunk,
+) -> Result<(), Vec<Diagnostic>> {
     let mut compiler = Compiler::new(code, chunk);
-    compiler.compile()
+
+    match compiler.compile() {
+        Ok(()) => Ok(()),
+        Err(_) => Err(compiler.diagnostics),
+    }
 }
 
 struct Compiler<'code> {
     parser: Parser<'code>,
     chunk: &'code mut Chunk,
     scanner: ScannerIterator<'code>,
+    fold_constants: bool,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Compiler<'a> {
@@ -25,12 +49,20 @@ impl<'a> Compiler<'a> {
             parser: Parser::new(),
             chunk,
             scanner: scanner.scan(code),
+            fold_constants: true,
+            diagnostics: vec![],
         }
     }
 
     fn compile(&mut self) -> Result<(), InterpretError> {
         self.advance()?;
         self.expression()?;
+
+        // Not `consume`, as there's nothing to advance to after the end.
+        if self.parser.current.as_ref().unwrap().token_type != TokenType::Eof {
+            self.error_at_current("Expect end of expression");
+        }
+
         self.end_compiler();
 
         if self.parser.had_error {
@@ -49,8 +81,8 @@ impl<'a> Compiler<'a> {
             match self.parser.current.as_ref() {
                 Some(token) if token.token_type != TokenType::Error => break,
                 Some(token) => {
-                    let code = token.code;
-                    self.error_at_current(code);
+                    let message = token.error_message().unwrap();
+                    self.error_at_current(message);
                 }
                 None => return Err(InterpretError::CompileError),
             }
@@ -75,8 +107,8 @@ impl<'a> Compiler<'a> {
             .previous
             .as_ref()
             .expect("Tried to report error on previous token but there wasn't any");
-        self.error_at(previous_token, message);
-        self.mark_error();
+        let diagnostic = Diagnostic::error_at(previous_token, message);
+        self.report(diagnostic);
     }
 
     fn error_at_current(&mut self, message: &str) {
@@ -85,24 +117,16 @@ impl<'a> Compiler<'a> {
             .current
             .as_ref()
             .expect("Tried to report error on current token but there wasn't any");
-        self.error_at(current_token, message);
-        self.mark_error();
+        let diagnostic = Diagnostic::error_at(current_token, message);
+        self.report(diagnostic);
     }
 
-    fn error_at(&self, token: &Token, message: &str) {
-        if self.parser.panic_mode {
-            return;
+    fn report(&mut self, diagnostic: Diagnostic) {
+        if !self.parser.panic_mode {
+            self.diagnostics.push(diagnostic);
         }
 
-        eprint!("[line {}] Error", token.line);
-
-        // The book here checks for Token::Eof which right now we don't have,
-        // we might need it later.
-        if token.token_type != TokenType::Error {
-            eprint!(" at '{}'", token.code);
-        }
-
-        eprintln!(": {}", message);
+        self.mark_error();
     }
 
     fn mark_error(&mut self) {
@@ -117,10 +141,6 @@ impl<'a> Compiler<'a> {
 
     fn end_compiler(&mut self) {
         self.emit_return();
-
-        if cfg!(debug_assertions) && !self.parser.had_error {
-            disassemble(&self.chunk, "code");
-        }
     }
 
     fn emit_return(&mut self) {
@@ -130,9 +150,19 @@ impl<'a> Compiler<'a> {
 
     fn emit_constant(&mut self, value: Value) {
         let line = self.parser.previous.as_ref().unwrap().line;
-        let constant_index = self.chunk.add_constant(value);
-        self.chunk
-            .add_instruction(Instruction::OpConstant(constant_index), line);
+        emit_value(self.chunk, value, line);
+    }
+
+    fn fold<F>(&mut self, start: usize, arity: usize, operator: F) -> bool
+    where
+        F: FnOnce(&[Value]) -> Option<Value>,
+    {
+        if !self.fold_constants {
+            return false;
+        }
+
+        let line = self.parser.previous.as_ref().unwrap().line;
+        fold_operands(self.chunk, start, line, arity, operator)
     }
 
     fn expression(&mut self) -> Result<(), InterpretError> {
@@ -142,12 +172,23 @@ impl<'a> Compiler<'a> {
     fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), InterpretError> {
         use TokenType::*;
 
+        // There's nothing after the end to advance to, so a missing operand
+        // at the end has to be reported before trying.
+        if self.parser.current.as_ref().unwrap().token_type == Eof {
+            self.error_at_current("Expect expression");
+            return Err(InterpretError::CompileError);
+        }
+
+        let start = self.chunk.instructions_count();
+        let can_assign = precedence <= Precedence::Assignment;
         self.advance()?;
 
         match self.parser.previous.as_ref() {
-            Some(token) if token.token_type == Minus => self.unary(),
-            Some(token) if token.token_type == Bang => self.unary(),
-            Some(token) if token.token_type == LeftParen => self.grouping(),
+            Some(token) if token.token_type == Minus => self.unary()?,
+            Some(token) if token.token_type == Bang => self.unary()?,
+            Some(token) if token.token_type == LeftParen => self.grouping()?,
+            Some(token) if token.token_type == LeftBracket => self.list()?,
+            Some(token) if token.token_type == LeftBrace => self.map()?,
             Some(token) if token.token_type == Number => {
                 let code = token.code;
                 self.number(code);
@@ -156,6 +197,10 @@ impl<'a> Compiler<'a> {
                 let code = token.code;
                 self.string(code);
             }
+            Some(token) if token.token_type == Identifier => {
+                let code = token.code;
+                self.variable(code);
+            }
             Some(token)
                 if token.token_type == False
                     || token.token_type == True
@@ -163,6 +208,9 @@ impl<'a> Compiler<'a> {
             {
                 self.literal()
             }
+            Some(token) if token.token_type == Import => {
+                self.error("Imports are only supported when running a file")
+            }
             _ => self.error("Expected prefix expression"),
         }
 
@@ -187,14 +235,23 @@ impl<'a> Compiler<'a> {
                         || token.token_type == GreaterEqual
                         || token.token_type == Less
                         || token.token_type == LessEqual
-                        || token.token_type == EqualEqual =>
+                        || token.token_type == EqualEqual
+                        || token.token_type == BangEqual =>
                 {
-                    self.binary()
+                    self.binary(start)?
                 }
+                Some(token) if token.token_type == LeftParen => self.call()?,
+                Some(token) if token.token_type == LeftBracket => self.index(can_assign)?,
+                Some(token) if token.token_type == Dot => self.property()?,
                 _ => self.error("Expected infix expression"),
             }
         }
 
+        if can_assign && self.parser.current.as_ref().unwrap().token_type == Equal {
+            self.advance()?;
+            self.error("Invalid assignment target");
+        }
+
         Ok(())
     }
 
@@ -206,6 +263,11 @@ impl<'a> Compiler<'a> {
         self.emit_constant(Value::from(&code[1..code.len() - 1]));
     }
 
+    fn variable(&mut self, name: &str) {
+        let constant_index = self.chunk.add_constant(Value::from(name));
+        self.emit_instruction(Instruction::OpGetGlobal(constant_index));
+    }
+
     fn literal(&mut self) {
         match self.parser.previous.as_ref().unwrap().token_type {
             TokenType::False => self.emit_instruction(Instruction::OpFalse),
@@ -215,12 +277,125 @@ impl<'a> Compiler<'a> {
         }
     }
 
-    fn grouping(&mut self) {
-        self.expression();
+    fn grouping(&mut self) -> Result<(), InterpretError> {
+        self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after expression");
+        Ok(())
     }
 
-    fn unary(&mut self) {
+    fn call(&mut self) -> Result<(), InterpretError> {
+        let arguments_count = self.expression_list(TokenType::RightParen)?;
+
+        if arguments_count > MAX_ARGUMENTS {
+            self.error("Can't have more than 255 arguments");
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after arguments");
+        self.emit_instruction(Instruction::OpCall(arguments_count));
+        Ok(())
+    }
+
+    fn list(&mut self) -> Result<(), InterpretError> {
+        let elements_count = self.expression_list(TokenType::RightBracket)?;
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements");
+        self.emit_instruction(Instruction::OpBuildList(elements_count));
+        Ok(())
+    }
+
+    /// Compiles a map literal. A `{` can only start a map where an expression
+    /// is expected, since blocks are never part of an expression.
+    fn map(&mut self) -> Result<(), InterpretError> {
+        let mut entries_count = 0;
+
+        if self.parser.current.as_ref().unwrap().token_type != TokenType::RightBrace {
+            loop {
+                self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after map key");
+                self.expression()?;
+                entries_count += 1;
+
+                if self.parser.current.as_ref().unwrap().token_type != TokenType::Comma {
+                    break;
+                }
+                self.advance()?;
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries");
+        self.emit_instruction(Instruction::OpBuildMap(entries_count));
+        Ok(())
+    }
+
+    /// Compiles comma-separated expressions up to a closing token, which is
+    /// left for the caller to consume, and returns how many there were.
+    fn expression_list(&mut self, closing: TokenType) -> Result<usize, InterpretError> {
+        let mut count = 0;
+
+        if self.parser.current.as_ref().unwrap().token_type != closing {
+            loop {
+                self.expression()?;
+                count += 1;
+
+                if self.parser.current.as_ref().unwrap().token_type != TokenType::Comma {
+                    break;
+                }
+                self.advance()?;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Compiles an index, or a slice when there's a colon, whose missing
+    /// bounds are loaded as nil.
+    fn index(&mut self, can_assign: bool) -> Result<(), InterpretError> {
+        let has_start = self.parser.current.as_ref().unwrap().token_type != TokenType::Colon;
+
+        if has_start {
+            self.expression()?;
+        }
+
+        if self.parser.current.as_ref().unwrap().token_type != TokenType::Colon {
+            self.consume(TokenType::RightBracket, "Expect ']' after index");
+
+            if can_assign && self.parser.current.as_ref().unwrap().token_type == TokenType::Equal {
+                self.advance()?;
+                self.expression()?;
+                self.emit_instruction(Instruction::OpSetIndex);
+            } else {
+                self.emit_instruction(Instruction::OpGetIndex);
+            }
+
+            return Ok(());
+        }
+
+        self.advance()?;
+
+        if !has_start {
+            self.emit_instruction(Instruction::OpNil);
+        }
+
+        if self.parser.current.as_ref().unwrap().token_type == TokenType::RightBracket {
+            self.emit_instruction(Instruction::OpNil);
+        } else {
+            self.expression()?;
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after slice");
+        self.emit_instruction(Instruction::OpSlice);
+        Ok(())
+    }
+
+    fn property(&mut self) -> Result<(), InterpretError> {
+        self.consume(TokenType::Identifier, "Expect property name after '.'");
+
+        let name = self.parser.previous.as_ref().unwrap().code;
+        let constant_index = self.chunk.add_constant(Value::from(name));
+        self.emit_instruction(Instruction::OpGetProperty(constant_index));
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<(), InterpretError> {
         let operator_type = self
             .parser
             .previous
@@ -228,16 +403,27 @@ impl<'a> Compiler<'a> {
             .expect("Did not find previous token when parsing unary expression")
             .token_type;
 
-        self.parse_precedence(Precedence::Unary);
-
-        match operator_type {
-            TokenType::Minus => self.emit_instruction(Instruction::OpNegate),
-            TokenType::Bang => self.emit_instruction(Instruction::OpNot),
+        let instruction = match operator_type {
+            TokenType::Minus => Instruction::OpNegate,
+            TokenType::Bang => Instruction::OpNot,
             _ => unimplemented!(),
+        };
+
+        let operand_start = self.chunk.instructions_count();
+        self.parse_precedence(Precedence::Unary)?;
+
+        if !self.fold(operand_start, 1, |operands| {
+            fold_unary(&instruction, &operands[0])
+        }) {
+            self.emit_instruction(instruction);
         }
+
+        Ok(())
     }
 
-    fn binary(&mut self) {
+    /// Compiles the right operand and the operator of a binary expression,
+    /// whose left operand starts at the instruction `left_start`.
+    fn binary(&mut self, left_start: usize) -> Result<(), InterpretError> {
         let operator_type = self
             .parser
             .previous
@@ -245,30 +431,137 @@ impl<'a> Compiler<'a> {
             .expect("Did not find previous token when parsing binary expression")
             .token_type;
 
-        self.parse_precedence(operator_type.precedence().higher());
+        let instruction = match operator_type {
+            TokenType::Plus => Instruction::OpAdd,
+            TokenType::Minus => Instruction::OpSubtract,
+            TokenType::Star => Instruction::OpMultiply,
+            TokenType::Slash => Instruction::OpDivide,
+            TokenType::BangEqual => Instruction::OpNotEqual,
+            TokenType::EqualEqual => Instruction::OpEqual,
+            TokenType::Greater => Instruction::OpGreater,
+            TokenType::GreaterEqual => Instruction::OpGreaterEqual,
+            TokenType::Less => Instruction::OpLess,
+            TokenType::LessEqual => Instruction::OpLessEqual,
+            _ => unimplemented!(),
+        };
+
+        self.parse_precedence(operator_type.precedence().higher())?;
+
+        if !self.fold(left_start, 2, |operands| {
+            fold_binary(&instruction, &operands[0], &operands[1])
+        }) {
+            self.emit_instruction(instruction);
+        }
+
+        Ok(())
+    }
+}
 
-        match operator_type {
-            TokenType::Plus => self.emit_instruction(Instruction::OpAdd),
-            TokenType::Minus => self.emit_instruction(Instruction::OpSubtract),
-            TokenType::Star => self.emit_instruction(Instruction::OpMultiply),
-            TokenType::Slash => self.emit_instruction(Instruction::OpDivide),
-            TokenType::BangEqual => {
-                self.emit_instruction(Instruction::OpEqual);
-                self.emit_instruction(Instruction::OpNot);
+/// Adds an instruction that loads `value`, using the dedicated instructions
+/// for booleans and nil.
+pub(crate) fn emit_value(chunk: &mut Chunk, value: Value, line: usize) {
+    match value {
+        Value::Boolean(true) => chunk.add_instruction(Instruction::OpTrue, line),
+        Value::Boolean(false) => chunk.add_instruction(Instruction::OpFalse, line),
+        Value::Nil => chunk.add_instruction(Instruction::OpNil, line),
+        value => {
+            let constant_index = chunk.add_constant(value);
+            chunk.add_instruction(Instruction::OpConstant(constant_index), line);
+        }
+    }
+}
+
+/// Tries to replace the operands emitted since `start` with the result of
+/// applying an operator to them at compile time. That's only possible when
+/// every operand is a literal, i.e. a single instruction that loads a
+/// constant, and when the operator wouldn't fail at runtime, in which case
+/// the code is left as is so that the VM reports the error.
+///
+/// The operator is only applied when there are exactly `arity` operands,
+/// which isn't the case after a syntax error left one of them out.
+pub(crate) fn fold_operands<F>(
+    chunk: &mut Chunk,
+    start: usize,
+    line: usize,
+    arity: usize,
+    operator: F,
+) -> bool
+where
+    F: FnOnce(&[Value]) -> Option<Value>,
+{
+    let operands: Option<Vec<Value>> = (start..chunk.instructions_count())
+        .map(|index| match chunk.instruction_at(index) {
+            Some(Instruction::OpConstant(constant_index)) => {
+                Some(chunk.constant_at(*constant_index).clone())
             }
-            TokenType::EqualEqual => self.emit_instruction(Instruction::OpEqual),
-            TokenType::Greater => self.emit_instruction(Instruction::OpGreater),
-            TokenType::GreaterEqual => {
-                self.emit_instruction(Instruction::OpLess);
-                self.emit_instruction(Instruction::OpNot);
+            Some(Instruction::OpTrue) => Some(Value::Boolean(true)),
+            Some(Instruction::OpFalse) => Some(Value::Boolean(false)),
+            Some(Instruction::OpNil) => Some(Value::Nil),
+            _ => None,
+        })
+        .collect();
+
+    let result = match operands {
+        Some(operands) if operands.len() == arity => operator(&operands),
+        _ => None,
+    };
+
+    let result = match result {
+        Some(result) => result,
+        None => return false,
+    };
+
+    while chunk.instructions_count() > start {
+        if let Some(Instruction::OpConstant(constant_index)) = chunk.pop_instruction() {
+            // Operands were just added to the pool, so their constants are
+            // the last ones in it.
+            if constant_index + 1 == chunk.constants_count() {
+                chunk.pop_constant();
             }
-            TokenType::Less => self.emit_instruction(Instruction::OpLess),
-            TokenType::LessEqual => {
-                self.emit_instruction(Instruction::OpGreater);
-                self.emit_instruction(Instruction::OpNot);
+        }
+    }
+
+    emit_value(chunk, result, line);
+    true
+}
+
+/// Evaluates a unary instruction at compile time. Returns `None` if the
+/// operand isn't valid for it.
+pub(crate) fn fold_unary(instruction: &Instruction, operand: &Value) -> Option<Value> {
+    match (instruction, operand) {
+        (Instruction::OpNegate, Value::Number(number)) => Some(Value::Number(-number)),
+        (Instruction::OpNot, operand) => Some(Value::Boolean(operand.is_falsey())),
+        _ => None,
+    }
+}
+
+/// Evaluates a binary instruction at compile time, following the same (IEEE
+/// 754) semantics as the VM. Returns `None` if the operands aren't valid for
+/// it.
+pub(crate) fn fold_binary(instruction: &Instruction, a: &Value, b: &Value) -> Option<Value> {
+    match (instruction, a, b) {
+        (Instruction::OpEqual, a, b) => Some(Value::Boolean(a == b)),
+        (Instruction::OpNotEqual, a, b) => Some(Value::Boolean(a != b)),
+        (Instruction::OpAdd, Value::Obj(a), Value::Obj(b)) => match (&a.value, &b.value) {
+            (ObjValue::String(a), ObjValue::String(b)) => Some(Value::from(format!("{}{}", a, b))),
+            _ => None,
+        },
+        (instruction, Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (*a, *b);
+
+            match instruction {
+                Instruction::OpAdd => Some(Value::Number(a + b)),
+                Instruction::OpSubtract => Some(Value::Number(a - b)),
+                Instruction::OpMultiply => Some(Value::Number(a * b)),
+                Instruction::OpDivide => Some(Value::Number(a / b)),
+                Instruction::OpGreater => Some(Value::Boolean(a > b)),
+                Instruction::OpGreaterEqual => Some(Value::Boolean(a >= b)),
+                Instruction::OpLess => Some(Value::Boolean(a < b)),
+                Instruction::OpLessEqual => Some(Value::Boolean(a <= b)),
+                _ => None,
             }
-            _ => unimplemented!(),
         }
+        _ => None,
     }
 }
 
@@ -306,7 +599,7 @@ pub enum Precedence {
 }
 
 impl Precedence {
-    fn higher(&self) -> Precedence {
+    pub(crate) fn higher(&self) -> Precedence {
         use Precedence::*;
 
         // TODO: Is this the best way to get the next higher precedence level?
@@ -331,6 +624,33 @@ impl Precedence {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rlox::parser::parse;
+
+    fn compile_without_folding(code: &str, chunk: &mut Chunk) -> Result<(), InterpretError> {
+        let mut compiler = Compiler::new(code, chunk);
+        compiler.fold_constants = false;
+        compiler.compile()
+    }
+
+    fn compile_to_constant(code: &str) -> Value {
+        let mut chunk = Chunk::new();
+        compile(code, &mut chunk).expect("Error compiling code");
+
+        let instructions: Vec<_> = chunk.instructions().collect();
+        assert_eq!(instructions.len(), 2, "{} was not folded", code);
+        assert_eq!(instructions[1], &Instruction::OpReturn);
+
+        match instructions[0] {
+            Instruction::OpConstant(index) => {
+                assert_eq!(chunk.constants_count(), 1);
+                chunk.constant_at(*index).clone()
+            }
+            Instruction::OpTrue => Value::Boolean(true),
+            Instruction::OpFalse => Value::Boolean(false),
+            Instruction::OpNil => Value::Nil,
+            instruction => panic!("Unexpected {:?}", instruction),
+        }
+    }
 
     #[test]
     fn test_compile_error() {
@@ -364,7 +684,7 @@ mod tests {
     #[test]
     fn test_compile_unary_operator() {
         let mut chunk = Chunk::new();
-        let result = compile("-123.4", &mut chunk);
+        let result = compile_without_folding("-123.4", &mut chunk);
         assert!(matches!(result, Ok(())));
 
         let mut instructions = chunk.instructions();
@@ -389,7 +709,7 @@ mod tests {
     #[test]
     fn test_compile_binary_operator() {
         let mut chunk = Chunk::new();
-        let result = compile("1 + 2", &mut chunk);
+        let result = compile_without_folding("1 + 2", &mut chunk);
         assert!(matches!(result, Ok(())));
 
         let mut instructions = chunk.instructions();
@@ -428,7 +748,7 @@ mod tests {
     #[test]
     fn test_arithmetic_precedence() {
         let mut chunk = Chunk::new();
-        let result = compile("1 + 2 * 3", &mut chunk);
+        let result = compile_without_folding("1 + 2 * 3", &mut chunk);
         assert!(matches!(result, Ok(())));
 
         let mut instructions = chunk.instructions();
@@ -481,7 +801,7 @@ mod tests {
     #[test]
     fn test_grouping() {
         let mut chunk = Chunk::new();
-        let result = compile("(1 + 2) * 3", &mut chunk);
+        let result = compile_without_folding("(1 + 2) * 3", &mut chunk);
         assert!(matches!(result, Ok(())));
 
         let mut instructions = chunk.instructions();
@@ -537,4 +857,249 @@ mod tests {
         let result = compile("(1 + 2", &mut chunk);
         assert!(matches!(result, Err(InterpretError::CompileError)));
     }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        assert_eq!(compile_to_constant("1 + 2 * 3"), Value::Number(7.0));
+        assert_eq!(compile_to_constant("(1 + 2) * 3"), Value::Number(9.0));
+        assert_eq!(compile_to_constant("-(4 - 6) / 4"), Value::Number(0.5));
+        assert_eq!(compile_to_constant("\"a\" + \"b\""), Value::from("ab"));
+    }
+
+    #[test]
+    fn test_fold_comparison_and_logic() {
+        assert_eq!(compile_to_constant("1 < 2"), Value::Boolean(true));
+        assert_eq!(compile_to_constant("2 <= 1"), Value::Boolean(false));
+        assert_eq!(compile_to_constant("!nil"), Value::Boolean(true));
+        assert_eq!(compile_to_constant("\"a\" == \"a\""), Value::Boolean(true));
+        assert_eq!(compile_to_constant("1 == true"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_fold_preserves_ieee_semantics() {
+        assert_eq!(compile_to_constant("1 / 0"), Value::Number(f64::INFINITY));
+        assert!(matches!(
+            compile_to_constant("0 / 0"),
+            Value::Number(n) if n.is_nan()
+        ));
+        assert_eq!(compile_to_constant("0 / 0 >= 1"), Value::Boolean(false));
+        assert_eq!(compile_to_constant("0 / 0 == 0 / 0"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_invalid_operands_are_not_folded() {
+        let mut chunk = Chunk::new();
+        compile("1 + 2 * \"a\"", &mut chunk).expect("Error compiling code");
+
+        let instructions: Vec<_> = chunk.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                &Instruction::OpConstant(0),
+                &Instruction::OpConstant(1),
+                &Instruction::OpConstant(2),
+                &Instruction::OpMultiply,
+                &Instruction::OpAdd,
+                &Instruction::OpReturn,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_operands_are_not_folded() {
+        for code in &["1 + )", "-)", "1 + (2 * )"] {
+            let mut chunk = Chunk::new();
+            assert_eq!(
+                compile(code, &mut chunk),
+                Err(InterpretError::CompileError),
+                "for {}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_trailing_tokens_are_rejected() {
+        for code in &["1 + 2 )", "1 2", "f() ]"] {
+            let mut chunk = Chunk::new();
+            assert_eq!(
+                compile(code, &mut chunk),
+                Err(InterpretError::CompileError),
+                "for {}",
+                code
+            );
+            assert!(parse(code).is_err(), "for {}", code);
+        }
+    }
+
+    #[test]
+    fn test_missing_expression_at_the_end_is_reported() {
+        for code in &["", "1 +", "(1 + 2) * -", "f(1,"] {
+            let mut chunk = Chunk::new();
+            let diagnostics = compile_with_diagnostics(code, &mut chunk).unwrap_err();
+            let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+
+            let column = code.len() + 1;
+            assert_eq!(
+                messages,
+                [format!("[line 1:{}] Error: Expect expression", column)],
+                "for {}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_fold_keeps_line_info() {
+        let mut chunk = Chunk::new();
+        compile("1 +\n2", &mut chunk).expect("Error compiling code");
+
+        assert_eq!(chunk.instructions_count(), 2);
+        assert_eq!(chunk.line_at(0), &2);
+        assert_eq!(chunk.line_at(1), &2);
+    }
+
+    #[test]
+    fn test_calls() {
+        let mut chunk = Chunk::new();
+        compile("f(1 + 2, g())\n+ 3", &mut chunk).expect("Error compiling code");
+
+        let instructions: Vec<_> = chunk.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                &Instruction::OpGetGlobal(0),
+                &Instruction::OpConstant(1),
+                &Instruction::OpGetGlobal(2),
+                &Instruction::OpCall(0),
+                &Instruction::OpCall(2),
+                &Instruction::OpConstant(3),
+                &Instruction::OpAdd,
+                &Instruction::OpReturn,
+            ]
+        );
+        assert_eq!(chunk.constant_at(0), &Value::from("f"));
+        assert_eq!(chunk.constant_at(1), &Value::Number(3.0));
+
+        let arguments = vec!["1"; MAX_ARGUMENTS + 1].join(", ");
+        let mut chunk = Chunk::new();
+        assert!(compile(&format!("f({})", arguments), &mut chunk).is_err());
+        assert!(compile("f(1, 2", &mut chunk).is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut chunk = Chunk::new();
+        compile("[1, []][0] = [2].len()", &mut chunk).expect("Error compiling code");
+
+        let instructions: Vec<_> = chunk.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                &Instruction::OpConstant(0),
+                &Instruction::OpBuildList(0),
+                &Instruction::OpBuildList(2),
+                &Instruction::OpConstant(1),
+                &Instruction::OpConstant(2),
+                &Instruction::OpBuildList(1),
+                &Instruction::OpGetProperty(3),
+                &Instruction::OpCall(0),
+                &Instruction::OpSetIndex,
+                &Instruction::OpReturn,
+            ]
+        );
+        assert_eq!(chunk.constant_at(3), &Value::from("len"));
+
+        for code in &["[1, 2", "[1][0", "1 + [1][0] = 2", "[1].2", "1 = 2"] {
+            let mut chunk = Chunk::new();
+            assert!(compile(code, &mut chunk).is_err(), "for {}", code);
+        }
+    }
+
+    #[test]
+    fn test_maps() {
+        let mut chunk = Chunk::new();
+        compile("{\"a\": 1, 2: {}}[\"a\"] = 3", &mut chunk).expect("Error compiling code");
+
+        let instructions: Vec<_> = chunk.instructions().collect();
+        assert_eq!(
+            instructions,
+            vec![
+                &Instruction::OpConstant(0),
+                &Instruction::OpConstant(1),
+                &Instruction::OpConstant(2),
+                &Instruction::OpBuildMap(0),
+                &Instruction::OpBuildMap(2),
+                &Instruction::OpConstant(3),
+                &Instruction::OpConstant(4),
+                &Instruction::OpSetIndex,
+                &Instruction::OpReturn,
+            ]
+        );
+
+        for code in &["{1}", "{1: 2", "{1: 2,}", "{: 2}"] {
+            let mut chunk = Chunk::new();
+            assert!(compile(code, &mut chunk).is_err(), "for {}", code);
+        }
+    }
+
+    #[test]
+    fn test_slices() {
+        for (code, bounds) in &[
+            (
+                "x[1:2]",
+                [Instruction::OpConstant(1), Instruction::OpConstant(2)],
+            ),
+            ("x[:2]", [Instruction::OpNil, Instruction::OpConstant(1)]),
+            ("x[1:]", [Instruction::OpConstant(1), Instruction::OpNil]),
+            ("x[:]", [Instruction::OpNil, Instruction::OpNil]),
+        ] {
+            let mut chunk = Chunk::new();
+            compile(code, &mut chunk).expect("Error compiling code");
+
+            let instructions: Vec<_> = chunk.instructions().collect();
+            assert_eq!(
+                instructions,
+                vec![
+                    &Instruction::OpGetGlobal(0),
+                    &bounds[0],
+                    &bounds[1],
+                    &Instruction::OpSlice,
+                    &Instruction::OpReturn,
+                ],
+                "for {}",
+                code
+            );
+        }
+
+        for code in &["x[1:2", "x[1:2:3]", "x[1:2] = 3", "x[::]"] {
+            let mut chunk = Chunk::new();
+            assert!(compile(code, &mut chunk).is_err(), "for {}", code);
+        }
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        for (code, expected) in &[
+            ("1 >= 2", Instruction::OpGreaterEqual),
+            ("1 <= 2", Instruction::OpLessEqual),
+            ("1 != 2", Instruction::OpNotEqual),
+        ] {
+            let mut chunk = Chunk::new();
+            compile_without_folding(code, &mut chunk).expect("Error compiling code");
+
+            let instructions: Vec<_> = chunk.instructions().collect();
+            assert_eq!(
+                instructions,
+                vec![
+                    &Instruction::OpConstant(0),
+                    &Instruction::OpConstant(1),
+                    expected,
+                    &Instruction::OpReturn,
+                ]
+            );
+        }
+
+        assert_eq!(compile_to_constant("1 != 2"), Value::Boolean(true));
+    }
 }
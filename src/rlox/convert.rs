@@ -0,0 +1,591 @@
+use crate::rlox::map::Map;
+use crate::rlox::value::{Obj, ObjValue, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Conversion from a Rust value into a Lox `Value`.
+pub trait IntoLox {
+    fn into_lox(self) -> Value;
+}
+
+/// Conversion from a Lox `Value` back into a Rust value. Unlike `IntoLox`
+/// this can fail, since a Lox value can hold any type at runtime.
+pub trait FromLox: Sized {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError>;
+}
+
+/// Conversion from a list of arguments, as received by a native function,
+/// into a tuple of Rust values.
+pub trait FromLoxArgs: Sized {
+    fn from_lox_args(args: &[Value]) -> Result<Self, ConversionError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConversionError {
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    NotAnInteger(f64),
+    OutOfRange {
+        value: f64,
+        target: &'static str,
+    },
+    ArityMismatch {
+        expected: usize,
+        found: usize,
+    },
+    Argument {
+        position: usize,
+        error: Box<ConversionError>,
+    },
+    Element {
+        index: usize,
+        error: Box<ConversionError>,
+    },
+    LengthMismatch {
+        expected: usize,
+        found: usize,
+    },
+    NonStringKey {
+        found: &'static str,
+    },
+    Entry {
+        key: String,
+        error: Box<ConversionError>,
+    },
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConversionError::TypeMismatch { expected, found } => {
+                write!(f, "Expected {} but found {}", expected, found)
+            }
+            ConversionError::NotAnInteger(value) => {
+                write!(f, "Expected an integer but found {}", value)
+            }
+            ConversionError::OutOfRange { value, target } => {
+                write!(f, "{} is out of range for {}", value, target)
+            }
+            ConversionError::ArityMismatch { expected, found } => {
+                write!(f, "Expected {} arguments but got {}", expected, found)
+            }
+            ConversionError::Argument { position, error } => {
+                write!(f, "Argument {}: {}", position + 1, error)
+            }
+            ConversionError::Element { index, error } => {
+                write!(f, "Element {}: {}", index, error)
+            }
+            ConversionError::LengthMismatch { expected, found } => write!(
+                f,
+                "Expected a list of {} elements but found {}",
+                expected, found
+            ),
+            ConversionError::NonStringKey { found } => {
+                write!(f, "Expected string keys but found a {} key", found)
+            }
+            ConversionError::Entry { key, error } => write!(f, "Key {:?}: {}", key, error),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+fn mismatch(expected: &'static str, value: &Value) -> ConversionError {
+    ConversionError::TypeMismatch {
+        expected,
+        found: value.type_name(),
+    }
+}
+
+impl IntoLox for Value {
+    fn into_lox(self) -> Value {
+        self
+    }
+}
+
+impl FromLox for Value {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl FromLox for () {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Nil => Ok(()),
+            _ => Err(mismatch("nil", value)),
+        }
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Boolean(boolean) => Ok(*boolean),
+            _ => Err(mismatch("boolean", value)),
+        }
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Number(number) => Ok(*number),
+            _ => Err(mismatch("number", value)),
+        }
+    }
+}
+
+impl IntoLox for f32 {
+    fn into_lox(self) -> Value {
+        Value::from(f64::from(self))
+    }
+}
+
+impl FromLox for f32 {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        f64::from_lox(value).map(|number| number as f32)
+    }
+}
+
+// Lox only has one number type, backed by an f64. Going from an integer to a
+// Lox number may lose precision for very large values, same as an `as` cast.
+// Going the other way is checked: the number has to be integral and fit in the
+// target type.
+macro_rules! impl_integer_conversions {
+    ($($t:ty),*) => {
+        $(
+            impl IntoLox for $t {
+                fn into_lox(self) -> Value {
+                    Value::Number(self as f64)
+                }
+            }
+
+            impl FromLox for $t {
+                fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+                    let number = f64::from_lox(value)?;
+                    let out_of_range = ConversionError::OutOfRange {
+                        value: number,
+                        target: stringify!($t),
+                    };
+
+                    if number.is_nan() || (number.is_finite() && number.fract() != 0.0) {
+                        return Err(ConversionError::NotAnInteger(number));
+                    }
+
+                    // The cast would saturate anything outside of this range,
+                    // infinities included, which no target type can hold
+                    // anyway.
+                    if !(i128::MIN as f64..i128::MAX as f64).contains(&number) {
+                        return Err(out_of_range);
+                    }
+
+                    <$t>::try_from(number as i128).map_err(|_| out_of_range)
+                }
+            }
+        )*
+    };
+}
+
+impl_integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoLox for String {
+    fn into_lox(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Obj(Obj {
+                value: ObjValue::String(string),
+            }) => Ok(string.clone()),
+            _ => Err(mismatch("string", value)),
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> Value {
+        match self {
+            Some(value) => value.into_lox(),
+            None => Value::Nil,
+        }
+    }
+}
+
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_lox(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Vec<T> {
+    fn into_lox(self) -> Value {
+        Value::from(self.into_iter().map(T::into_lox).collect::<Vec<_>>())
+    }
+}
+
+/// Converts a list into a new vector, so changes to one don't show in the
+/// other.
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        let list = match value {
+            Value::Obj(Obj {
+                value: ObjValue::List(list),
+            }) => list,
+            _ => return Err(mismatch("list", value)),
+        };
+
+        list.borrow()
+            .iter()
+            .enumerate()
+            .map(|(index, element)| {
+                T::from_lox(element).map_err(|error| ConversionError::Element {
+                    index,
+                    error: Box::new(error),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Converts a hash map into a map with its keys in sorted order, since a hash
+/// map doesn't have an order of its own.
+impl<T: IntoLox> IntoLox for HashMap<String, T> {
+    fn into_lox(self) -> Value {
+        let mut entries: Vec<(String, T)> = self.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut map = Map::new();
+        for (key, value) in entries {
+            map.insert(Value::from(key), value.into_lox())
+                .expect("Strings are valid map keys");
+        }
+
+        Value::from(map)
+    }
+}
+
+/// Converts a map whose keys are all strings into a new hash map.
+impl<T: FromLox> FromLox for HashMap<String, T> {
+    fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+        let map = match value {
+            Value::Obj(Obj {
+                value: ObjValue::Map(map),
+            }) => map,
+            _ => return Err(mismatch("map", value)),
+        };
+
+        map.borrow()
+            .iter()
+            .map(|(key, value)| {
+                let key = String::from_lox(key).map_err(|_| ConversionError::NonStringKey {
+                    found: key.type_name(),
+                })?;
+                let value = T::from_lox(value).map_err(|error| ConversionError::Entry {
+                    key: key.clone(),
+                    error: Box::new(error),
+                })?;
+
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+// Tuples convert to and from lists of a fixed length, e.g. for natives that
+// return several values at once. The empty tuple is nil, as defined above.
+macro_rules! impl_tuple_conversions {
+    ($count:expr; $($t:ident => $index:tt),*) => {
+        impl<$($t: IntoLox),*> IntoLox for ($($t,)*) {
+            fn into_lox(self) -> Value {
+                Value::from(vec![$(self.$index.into_lox()),*])
+            }
+        }
+
+        impl<$($t: FromLox),*> FromLox for ($($t,)*) {
+            fn from_lox(value: &Value) -> Result<Self, ConversionError> {
+                let list = match value {
+                    Value::Obj(Obj {
+                        value: ObjValue::List(list),
+                    }) => list.borrow(),
+                    _ => return Err(mismatch("list", value)),
+                };
+
+                if list.len() != $count {
+                    return Err(ConversionError::LengthMismatch {
+                        expected: $count,
+                        found: list.len(),
+                    });
+                }
+
+                Ok(($(
+                    $t::from_lox(&list[$index]).map_err(|error| ConversionError::Element {
+                        index: $index,
+                        error: Box::new(error),
+                    })?,
+                )*))
+            }
+        }
+    };
+}
+
+impl_tuple_conversions!(1; A => 0);
+impl_tuple_conversions!(2; A => 0, B => 1);
+impl_tuple_conversions!(3; A => 0, B => 1, C => 2);
+impl_tuple_conversions!(4; A => 0, B => 1, C => 2, D => 3);
+impl_tuple_conversions!(5; A => 0, B => 1, C => 2, D => 3, E => 4);
+
+macro_rules! impl_from_lox_args {
+    ($count:expr; $($t:ident => $index:tt),*) => {
+        impl<$($t: FromLox),*> FromLoxArgs for ($($t,)*) {
+            #[allow(unused_variables)]
+            fn from_lox_args(args: &[Value]) -> Result<Self, ConversionError> {
+                if args.len() != $count {
+                    return Err(ConversionError::ArityMismatch {
+                        expected: $count,
+                        found: args.len(),
+                    });
+                }
+
+                Ok(($(
+                    $t::from_lox(&args[$index]).map_err(|error| ConversionError::Argument {
+                        position: $index,
+                        error: Box::new(error),
+                    })?,
+                )*))
+            }
+        }
+    };
+}
+
+impl_from_lox_args!(0;);
+impl_from_lox_args!(1; A => 0);
+impl_from_lox_args!(2; A => 0, B => 1);
+impl_from_lox_args!(3; A => 0, B => 1, C => 2);
+impl_from_lox_args!(4; A => 0, B => 1, C => 2, D => 3);
+impl_from_lox_args!(5; A => 0, B => 1, C => 2, D => 3, E => 4);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_round_trip() {
+        let value = 42i32.into_lox();
+        assert_eq!(value, Value::Number(42.0));
+        assert_eq!(i32::from_lox(&value), Ok(42));
+    }
+
+    #[test]
+    fn test_integer_rejects_fractions_and_overflow() {
+        assert_eq!(
+            i64::from_lox(&Value::Number(1.5)),
+            Err(ConversionError::NotAnInteger(1.5))
+        );
+        assert_eq!(
+            u8::from_lox(&Value::Number(256.0)),
+            Err(ConversionError::OutOfRange {
+                value: 256.0,
+                target: "u8"
+            })
+        );
+        assert_eq!(
+            u32::from_lox(&Value::Number(-1.0)),
+            Err(ConversionError::OutOfRange {
+                value: -1.0,
+                target: "u32"
+            })
+        );
+    }
+
+    #[test]
+    fn test_integer_rejects_huge_and_infinite_numbers() {
+        for number in &[1e300, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(
+                i64::from_lox(&Value::Number(*number)),
+                Err(ConversionError::OutOfRange {
+                    value: *number,
+                    target: "i64"
+                })
+            );
+        }
+
+        assert_eq!(
+            u64::from_lox(&Value::Number(f64::NAN)).map_err(|e| e.to_string()),
+            Err(String::from("Expected an integer but found NaN"))
+        );
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        assert_eq!(
+            f64::from_lox(&Value::from("hi")),
+            Err(ConversionError::TypeMismatch {
+                expected: "number",
+                found: "string"
+            })
+        );
+    }
+
+    #[test]
+    fn test_option() {
+        assert_eq!(Option::<f64>::from_lox(&Value::Nil), Ok(None));
+        assert_eq!(Option::<f64>::from_lox(&Value::Number(1.0)), Ok(Some(1.0)));
+        assert_eq!(None::<bool>.into_lox(), Value::Nil);
+        assert_eq!(Some("hi").into_lox(), Value::from("hi"));
+    }
+
+    #[test]
+    fn test_vec() {
+        let value = vec![1u8, 2].into_lox();
+        assert_eq!(
+            value,
+            Value::from(vec![Value::Number(1.0), Value::Number(2.0)])
+        );
+        assert_eq!(Vec::<u8>::from_lox(&value), Ok(vec![1, 2]));
+
+        let value = Value::from(vec![Value::Number(1.0), Value::Nil]);
+        assert_eq!(
+            Vec::<f64>::from_lox(&value),
+            Err(ConversionError::Element {
+                index: 1,
+                error: Box::new(ConversionError::TypeMismatch {
+                    expected: "number",
+                    found: "nil"
+                })
+            })
+        );
+        assert_eq!(
+            Vec::<f64>::from_lox(&Value::from("12")),
+            Err(ConversionError::TypeMismatch {
+                expected: "list",
+                found: "string"
+            })
+        );
+    }
+
+    #[test]
+    fn test_hash_map() {
+        let mut hash_map = HashMap::new();
+        hash_map.insert(String::from("b"), 2.0);
+        hash_map.insert(String::from("a"), 1.0);
+
+        let value = hash_map.clone().into_lox();
+        assert_eq!(value.to_string(), "{\"a\": 1, \"b\": 2}");
+        assert_eq!(HashMap::<String, f64>::from_lox(&value), Ok(hash_map));
+
+        let mut map = Map::new();
+        map.insert(Value::from("a"), Value::Nil).unwrap();
+        assert_eq!(
+            HashMap::<String, f64>::from_lox(&Value::from(map)),
+            Err(ConversionError::Entry {
+                key: String::from("a"),
+                error: Box::new(ConversionError::TypeMismatch {
+                    expected: "number",
+                    found: "nil"
+                })
+            })
+        );
+
+        let mut map = Map::new();
+        map.insert(Value::Number(1.0), Value::Nil).unwrap();
+        assert_eq!(
+            HashMap::<String, Value>::from_lox(&Value::from(map)),
+            Err(ConversionError::NonStringKey { found: "number" })
+        );
+    }
+
+    #[test]
+    fn test_tuple() {
+        let value = (1u8, "a", true).into_lox();
+        assert_eq!(value.to_string(), "[1, \"a\", true]");
+        assert_eq!(
+            <(u8, String, bool)>::from_lox(&value),
+            Ok((1, String::from("a"), true))
+        );
+
+        assert_eq!(
+            <(u8, bool)>::from_lox(&value),
+            Err(ConversionError::LengthMismatch {
+                expected: 2,
+                found: 3
+            })
+        );
+        assert_eq!(
+            <(u8, u8, bool)>::from_lox(&value),
+            Err(ConversionError::Element {
+                index: 1,
+                error: Box::new(ConversionError::TypeMismatch {
+                    expected: "number",
+                    found: "string"
+                })
+            })
+        );
+        assert_eq!(
+            <(u8,)>::from_lox(&Value::Number(1.0)),
+            Err(ConversionError::TypeMismatch {
+                expected: "list",
+                found: "number"
+            })
+        );
+    }
+
+    #[test]
+    fn test_args_tuple() {
+        let args = vec![Value::from("hi"), Value::Number(2.0)];
+        assert_eq!(
+            <(String, usize)>::from_lox_args(&args),
+            Ok((String::from("hi"), 2))
+        );
+        assert_eq!(
+            <(String,)>::from_lox_args(&args),
+            Err(ConversionError::ArityMismatch {
+                expected: 1,
+                found: 2
+            })
+        );
+        assert_eq!(
+            <(String, bool)>::from_lox_args(&args),
+            Err(ConversionError::Argument {
+                position: 1,
+                error: Box::new(ConversionError::TypeMismatch {
+                    expected: "boolean",
+                    found: "number"
+                })
+            })
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::compiler::compile;
+use crate::rlox::trace::VmHook;
+use crate::rlox::value::Value;
+use crate::rlox::vm::Vm;
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+/// How far execution should go before the debugger stops again.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Mode {
+    /// Stop at the next line, even if it's inside a call.
+    StepInto,
+    /// Stop at the next line that isn't inside a call made from `depth`.
+    StepOver { depth: usize },
+    /// Stop once the call at `depth` returns.
+    StepOut { depth: usize },
+    /// Only stop at breakpoints.
+    Continue,
+}
+
+/// A line-based interactive debugger. It hooks into the VM and gets a chance
+/// to stop before each instruction, at which point it reads commands until
+/// one of them resumes execution.
+pub struct Debugger {
+    source: Vec<String>,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    depth: usize,
+    last_line: Option<usize>,
+    quit: bool,
+}
+
+impl Debugger {
+    pub fn new(source: &str, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Debugger {
+        Debugger {
+            source: source.lines().map(String::from).collect(),
+            input,
+            output,
+            breakpoints: BTreeSet::new(),
+            mode: Mode::StepInto,
+            depth: 0,
+            last_line: None,
+            quit: false,
+        }
+    }
+
+    fn should_pause(&self, line: usize, is_new_line: bool) -> bool {
+        match self.mode {
+            Mode::StepInto => is_new_line,
+            Mode::StepOver { depth } => is_new_line && self.depth <= depth,
+            Mode::StepOut { depth } => self.depth < depth,
+            Mode::Continue => is_new_line && self.breakpoints.contains(&line),
+        }
+    }
+
+    fn show_line(&mut self, line: usize) {
+        let source = &self.source;
+        let code = line
+            .checked_sub(1)
+            .and_then(|index| source.get(index))
+            .map(String::as_str)
+            .unwrap_or("");
+        let _ = writeln!(self.output, "[line {}] {}", line, code);
+    }
+
+    /// Reads and runs commands until one of them resumes execution.
+    fn prompt(&mut self, vm: &Vm) {
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+
+            let mut input = String::new();
+            match self.input.read_line(&mut input) {
+                Ok(0) | Err(_) => {
+                    // Without more commands there's nothing else to do but
+                    // let the script run to completion.
+                    self.breakpoints.clear();
+                    self.mode = Mode::Continue;
+                    return;
+                }
+                Ok(_) => {}
+            }
+
+            let input = input.trim();
+            let (command, argument) = match input.find(char::is_whitespace) {
+                Some(index) => (&input[..index], input[index..].trim()),
+                None => (input, ""),
+            };
+
+            match command {
+                "" => {}
+                "b" | "break" => match argument.parse::<usize>() {
+                    Ok(line) if line > 0 => {
+                        self.breakpoints.insert(line);
+                        let _ = writeln!(self.output, "Breakpoint set at line {}", line);
+                    }
+                    _ => {
+                        let _ = writeln!(self.output, "Usage: break <line>");
+                    }
+                },
+                "d" | "delete" => match argument.parse::<usize>() {
+                    Ok(line) if self.breakpoints.remove(&line) => {
+                        let _ = writeln!(self.output, "Breakpoint at line {} removed", line);
+                    }
+                    _ => {
+                        let _ = writeln!(self.output, "No breakpoint at line '{}'", argument);
+                    }
+                },
+                "s" | "step" => {
+                    self.mode = Mode::StepInto;
+                    return;
+                }
+                "n" | "next" => {
+                    self.mode = Mode::StepOver { depth: self.depth };
+                    return;
+                }
+                "o" | "out" => {
+                    self.mode = Mode::StepOut { depth: self.depth };
+                    return;
+                }
+                "c" | "continue" => {
+                    self.mode = Mode::Continue;
+                    return;
+                }
+                "stack" => {
+                    for value in vm.stack() {
+                        let _ = write!(self.output, "[ {} ]", value);
+                    }
+
+                    let _ = writeln!(self.output);
+                }
+                // Only the top level script can run for now, so there are no
+                // frames with locals to show.
+                "locals" => {
+                    let _ = writeln!(self.output, "No locals in the current frame");
+                }
+                "e" | "eval" => self.evaluate(vm, argument),
+                "q" | "quit" => {
+                    self.quit = true;
+                    return;
+                }
+                "h" | "help" => {
+                    let _ = writeln!(
+                        self.output,
+                        "break <line>, delete <line>, step, next, out, continue, \
+                         stack, locals, eval <expr>, quit"
+                    );
+                }
+                _ => {
+                    let _ = writeln!(self.output, "Unknown command '{}', try help", command);
+                }
+            }
+        }
+    }
+
+    /// Evaluates an expression with the globals of the paused VM. It runs in
+    /// a VM of its own, so that it can't disturb the stack of the program
+    /// being debugged.
+    fn evaluate(&mut self, paused: &Vm, code: &str) {
+        let mut chunk = Chunk::new();
+
+        if compile(code, &mut chunk).is_err() {
+            return;
+        }
+
+        let mut vm = Vm::bare();
+        for (name, value) in paused.globals() {
+            vm.define_global(name, value.clone());
+        }
+
+        if let Ok(value) = vm.interpret(&chunk) {
+            let _ = writeln!(self.output, "{}", value);
+        }
+    }
+}
+
+impl VmHook for Debugger {
+    fn on_call(&mut self, _name: &str) {
+        self.depth += 1;
+    }
+
+    fn on_instruction(&mut self, vm: &Vm, chunk: &Chunk, index: usize) {
+        let line = *chunk.line_at(index);
+        let is_new_line = self.last_line != Some(line);
+        self.last_line = Some(line);
+
+        if self.should_pause(line, is_new_line) {
+            self.show_line(line);
+            self.prompt(vm);
+        }
+    }
+
+    fn on_return(&mut self, value: &Value) {
+        self.depth -= 1;
+
+        if self.depth == 0 {
+            let _ = writeln!(self.output, "Program finished with {}", value);
+        }
+    }
+
+    fn on_runtime_error(&mut self, message: &str, line: usize) {
+        self.depth = 0;
+        let _ = writeln!(self.output, "Runtime error: {}", message);
+        self.show_line(line);
+    }
+
+    fn should_stop(&self) -> bool {
+        self.quit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::assembler::assemble;
+    use crate::rlox::vm::InterpretError;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs the chunk described by `assembly` under the debugger, showing
+    /// `code` as its source, and returns everything the debugger printed
+    /// along with the result of the run.
+    fn run_debugger(
+        vm: &mut Vm,
+        code: &str,
+        assembly: &str,
+        commands: &str,
+    ) -> (String, Result<Value, InterpretError>) {
+        let output = Rc::new(RefCell::new(vec![]));
+        let debugger = Debugger::new(
+            code,
+            Box::new(Cursor::new(commands.as_bytes().to_vec())),
+            Box::new(SharedOutput(Rc::clone(&output))),
+        );
+
+        let chunk = assemble(assembly).expect("Error assembling code");
+
+        vm.set_hook(Box::new(debugger));
+        let result = vm.interpret(&chunk);
+
+        let output = output.borrow();
+        (String::from_utf8(output.clone()).unwrap(), result)
+    }
+
+    fn debug(code: &str, assembly: &str, commands: &str) -> String {
+        let (output, result) = run_debugger(&mut Vm::new(), code, assembly, commands);
+        result.expect("Error running chunk");
+        output
+    }
+
+    // The compiler would fold this into a single constant, so the chunk is
+    // written by hand to have something to step through.
+    const THREE_LINES: &str = "1 +\n2 *\n3";
+    const THREE_LINES_ASSEMBLY: &str = "1 OpConstant 1
+                                        2 OpConstant 2
+                                        3 OpConstant 3
+                                          OpMultiply
+                                          OpAdd
+                                          OpReturn";
+
+    #[test]
+    fn test_stepping() {
+        let output = debug(THREE_LINES, THREE_LINES_ASSEMBLY, "s\nstack\ns\nstack\nc\n");
+
+        assert_eq!(
+            output,
+            "[line 1] 1 +\n\
+             (debug) [line 2] 2 *\n\
+             (debug) [ 1 ]\n\
+             (debug) [line 3] 3\n\
+             (debug) [ 1 ][ 2 ]\n\
+             (debug) Program finished with 7\n"
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let output = debug(THREE_LINES, THREE_LINES_ASSEMBLY, "b 3\nc\nstack\nc\n");
+
+        assert_eq!(
+            output,
+            "[line 1] 1 +\n\
+             (debug) Breakpoint set at line 3\n\
+             (debug) [line 3] 3\n\
+             (debug) [ 1 ][ 2 ]\n\
+             (debug) Program finished with 7\n"
+        );
+    }
+
+    #[test]
+    fn test_eval_expression() {
+        let output = debug("1", "OpConstant 1\nOpReturn", "e 2 * 3\nc\n");
+
+        assert_eq!(
+            output,
+            "[line 1] 1\n\
+             (debug) 6\n\
+             (debug) Program finished with 1\n"
+        );
+    }
+
+    #[test]
+    fn test_eval_sees_the_paused_globals() {
+        let mut vm = Vm::new();
+        vm.define_global("answer", Value::Number(41.0));
+
+        let (output, result) = run_debugger(
+            &mut vm,
+            "answer",
+            "OpGetGlobal \"answer\"\nOpReturn",
+            "e answer + 1\ne sqrt(answer + 8)\nc\n",
+        );
+
+        assert_eq!(result, Ok(Value::Number(41.0)));
+        assert_eq!(
+            output,
+            "[line 1] answer\n\
+             (debug) 42\n\
+             (debug) 7\n\
+             (debug) Program finished with 41\n"
+        );
+    }
+
+    #[test]
+    fn test_quit_stops_the_program() {
+        let (output, result) =
+            run_debugger(&mut Vm::new(), THREE_LINES, THREE_LINES_ASSEMBLY, "s\nq\n");
+
+        assert_eq!(
+            output,
+            "[line 1] 1 +\n\
+             (debug) [line 2] 2 *\n\
+             (debug) "
+        );
+        assert_eq!(result, Err(InterpretError::Stopped));
+    }
+
+    #[test]
+    fn test_line_zero() {
+        let output = debug("1", "0 OpConstant 1\nOpReturn", "c\n");
+        assert_eq!(output, "[line 0] \n(debug) Program finished with 1\n");
+    }
+
+    #[test]
+    fn test_runs_to_completion_without_input() {
+        let output = debug(
+            "1 +\n2",
+            "1 OpConstant 1\n2 OpConstant 2\nOpAdd\nOpReturn",
+            "",
+        );
+
+        assert_eq!(output, "[line 1] 1 +\n(debug) Program finished with 3\n");
+    }
+}
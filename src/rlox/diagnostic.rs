@@ -0,0 +1,73 @@
+use crate::rlox::token::{Token, TokenType};
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the source, reported at a line and column. Compile
+/// errors and lint warnings are both shown in this format:
+///
+/// ```text
+/// [line 1:5] Error at ')': Expected prefix expression
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    pub column: usize,
+    /// The code of the token the problem is at, if there's one to show.
+    pub token: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// An error at a token. The code of error tokens is what the scanner
+    /// couldn't make sense of, which the message already describes, and the
+    /// end of the file has no code, so neither is shown.
+    pub fn error_at(token: &Token, message: &str) -> Diagnostic {
+        let code = match token.token_type {
+            TokenType::Error | TokenType::Eof => None,
+            _ => Some(String::from(token.code)),
+        };
+
+        Diagnostic {
+            severity: Severity::Error,
+            line: token.start_line(),
+            column: token.column,
+            token: code,
+            message: String::from(message),
+        }
+    }
+
+    pub fn warning(line: usize, column: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            line,
+            column,
+            token: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+
+        write!(f, "[line {}:{}] {}", self.line, self.column, severity)?;
+
+        if let Some(token) = &self.token {
+            write!(f, " at '{}'", token)?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
@@ -1,8 +1,47 @@
 use super::chunk::Chunk;
-use super::instruction::Instruction;
+use super::value::{ObjValue, Value};
+use std::io;
+use std::io::Write;
 
-pub fn disassemble(chunk: &Chunk, name: &str) {
-    println!("== {} ==", name);
+/// A decoded instruction, with everything needed to display it without
+/// going back to the chunk.
+#[derive(Debug, PartialEq)]
+pub struct InstructionRecord<'a> {
+    pub offset: usize,
+    pub line: usize,
+    pub name: &'static str,
+    pub operands: Vec<usize>,
+    pub constant: Option<&'a Value>,
+}
+
+pub fn decode_instruction(chunk: &Chunk, index: usize) -> InstructionRecord<'_> {
+    let instruction = chunk
+        .instruction_at(index)
+        .expect("Tried to get instruction at wrong index");
+
+    let (operands, constant) = match instruction.constant_operand() {
+        Some(constant_index) => (
+            vec![constant_index],
+            Some(chunk.constant_at(constant_index)),
+        ),
+        None => (instruction.count_operand().into_iter().collect(), None),
+    };
+
+    InstructionRecord {
+        offset: index,
+        line: *chunk.line_at(index),
+        name: instruction.name(),
+        operands,
+        constant,
+    }
+}
+
+pub fn instruction_records(chunk: &Chunk) -> impl Iterator<Item = InstructionRecord<'_>> {
+    (0..chunk.instructions_count()).map(move |index| decode_instruction(chunk, index))
+}
+
+pub fn disassemble(chunk: &Chunk, name: &str, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "== {} ==", name)?;
 
     // For now, the chunk holds a vec of instructions. Instructions are an
     // enum, meaning that the size of each value is the size of the largest
@@ -12,14 +51,20 @@ pub fn disassemble(chunk: &Chunk, name: &str) {
     // It also has the side effect that for now there's no real "offset" other
     // than the index, so the disaseembly here will be a bit of a lie.
     for i in 0..chunk.instructions_count() {
-        disassemble_instruction(chunk, i);
+        disassemble_instruction(chunk, i, out)?;
     }
+
+    Ok(())
 }
 
-pub fn disassemble_instruction(chunk: &Chunk, index: usize) {
-    let instruction = chunk
-        .instruction_at(index)
-        .expect("Tried to get instruction at wrong index");
+pub fn disassemble_to_string(chunk: &Chunk, name: &str) -> String {
+    let mut out = vec![];
+    disassemble(chunk, name, &mut out).expect("Writing to a Vec can't fail");
+    String::from_utf8(out).expect("Disassembly is always valid UTF-8")
+}
+
+pub fn disassemble_instruction(chunk: &Chunk, index: usize, out: &mut dyn Write) -> io::Result<()> {
+    let record = decode_instruction(chunk, index);
 
     let last_line = if index >= 1 {
         Some(chunk.line_at(index - 1))
@@ -27,34 +72,102 @@ pub fn disassemble_instruction(chunk: &Chunk, index: usize) {
         None
     };
 
-    let line = chunk.line_at(index);
-
-    print!("{:0>4} ", index);
+    write!(out, "{:0>4} ", record.offset)?;
 
-    if last_line.map_or(false, |actual_last_line| actual_last_line == line) {
-        print!("   | ");
+    if last_line == Some(&record.line) {
+        write!(out, "   | ")?;
     } else {
-        print!("{: >4} ", line);
+        write!(out, "{: >4} ", record.line)?;
     }
 
-    match instruction {
-        Instruction::OpReturn => println!("OpReturn"),
-        Instruction::OpConstant(index) => {
-            print!("{: <16}", "OpConstant");
-            print!("{: >4}", index);
-            println!(" {:?}", chunk.constant_at(*index));
+    match (record.operands.first(), record.constant) {
+        (Some(operand), Some(constant)) => {
+            write!(out, "{: <16}", record.name)?;
+            write!(out, "{: >4}", operand)?;
+            writeln!(out, " {}", constant_literal(constant))
+        }
+        (Some(operand), None) => {
+            write!(out, "{: <16}", record.name)?;
+            writeln!(out, "{: >4}", operand)
         }
-        Instruction::OpNegate => println!("OpNegate"),
-        Instruction::OpAdd => println!("OpAdd"),
-        Instruction::OpSubtract => println!("OpSubtract"),
-        Instruction::OpMultiply => println!("OpMultiply"),
-        Instruction::OpDivide => println!("OpDivide"),
-        Instruction::OpTrue => println!("OpTrue"),
-        Instruction::OpFalse => println!("OpFalse"),
-        Instruction::OpNil => println!("OpNil"),
-        Instruction::OpNot => println!("OpNot"),
-        Instruction::OpEqual => println!("OpEqual"),
-        Instruction::OpLess => println!("OpLess"),
-        Instruction::OpGreater => println!("OpGreater"),
+        _ => writeln!(out, "{}", record.name),
+    }
+}
+
+/// Writes a constant the way it would be written in the source, so that the
+/// assembler can read it back. Strings get their quotes, backslashes and line
+/// breaks escaped so that they always fit in a single line.
+pub fn constant_literal(value: &Value) -> String {
+    match value {
+        Value::Obj(obj) => match &obj.value {
+            ObjValue::String(string) => {
+                let mut literal = String::from("\"");
+
+                for c in string.chars() {
+                    match c {
+                        '"' => literal.push_str("\\\""),
+                        '\\' => literal.push_str("\\\\"),
+                        '\n' => literal.push_str("\\n"),
+                        '\r' => literal.push_str("\\r"),
+                        '\t' => literal.push_str("\\t"),
+                        c => literal.push(c),
+                    }
+                }
+
+                literal.push('"');
+                literal
+            }
+            _ => value.to_string(),
+        },
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::instruction::Instruction;
+
+    fn sample_chunk() -> Chunk {
+        let mut chunk = Chunk::new();
+        let constant_index = chunk.add_constant(Value::Number(1.5));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(Instruction::OpNegate, 1);
+        chunk.add_instruction(Instruction::OpReturn, 2);
+        chunk
+    }
+
+    #[test]
+    fn test_disassemble_to_string() {
+        let output = disassemble_to_string(&sample_chunk(), "test");
+
+        assert_eq!(
+            output,
+            "== test ==\n\
+             0000    1 OpConstant         0 1.5\n\
+             0001    | OpNegate\n\
+             0002    2 OpReturn\n"
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        let chunk = sample_chunk();
+        let records: Vec<_> = instruction_records(&chunk).collect();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0],
+            InstructionRecord {
+                offset: 0,
+                line: 1,
+                name: "OpConstant",
+                operands: vec![0],
+                constant: Some(&Value::Number(1.5)),
+            }
+        );
+        assert_eq!(records[2].name, "OpReturn");
+        assert_eq!(records[2].line, 2);
+        assert!(records[2].operands.is_empty());
     }
 }
@@ -0,0 +1,333 @@
+use crate::rlox::ast::BinaryOperator;
+use crate::rlox::diagnostic::Diagnostic;
+use crate::rlox::parser::parse;
+use crate::rlox::scanner::Scanner;
+use crate::rlox::token::{Token, TokenType};
+
+/// Lines are wrapped after a binary operator once they would get longer
+/// than this many characters.
+const MAX_WIDTH: usize = 80;
+
+/// How much lines that continue an expression are indented.
+const CONTINUATION_INDENT: &str = "    ";
+
+/// Rewrites the code in the canonical style:
+///
+/// - binary operators and `=` are surrounded by single spaces, while unary
+///   operators, parentheses, brackets, braces and dots stick to their operands
+/// - call arguments, list elements and map entries are separated by a comma
+///   and a space, and map keys are followed by a colon and a space, while the
+///   colon of a slice sticks to its bounds
+/// - the whole expression goes on one line, wrapped after a binary operator
+///   or a comma when it gets too long, with continuation lines indented
+/// - comments are kept where they were, either at the end of a line or on a
+///   line of their own, and a blank line next to a comment is kept
+/// - imports go on lines of their own, with a blank line after the last one
+///
+/// Code that doesn't parse is left alone and the error is returned instead.
+pub fn format_code(code: &str) -> Result<String, Diagnostic> {
+    parse(code)?;
+
+    let mut scanner = Scanner::new();
+    let tokens: Vec<Token> = scanner.scan_with_comments(code).collect();
+    let last_code_token = tokens
+        .iter()
+        .rposition(|token| !matches!(token.token_type, TokenType::Comment | TokenType::Eof));
+
+    let mut formatter = Formatter::default();
+
+    for (index, token) in tokens.iter().enumerate() {
+        formatter.token(token);
+
+        if Some(index) == last_code_token {
+            formatter.in_expression = false;
+        }
+    }
+
+    Ok(formatter.finish())
+}
+
+#[derive(Default)]
+struct Formatter {
+    output: String,
+    line: String,
+    /// The type of the previous token, and whether it was a unary operator.
+    previous: Option<(TokenType, bool)>,
+    /// The source line the previous token ended on.
+    previous_line: usize,
+    /// Whether the expression has started and isn't over yet, so that new
+    /// lines need to be indented as continuations.
+    in_expression: bool,
+    in_import: bool,
+    /// The parentheses, brackets and braces that are still open, innermost
+    /// last.
+    delimiters: Vec<TokenType>,
+}
+
+impl Formatter {
+    fn token(&mut self, token: &Token) {
+        let start_line = token.start_line();
+        let after_blank_line = self.previous.is_some() && start_line > self.previous_line + 1;
+
+        match token.token_type {
+            TokenType::Eof => return,
+            TokenType::Comment => {
+                let after_code = matches!(
+                    self.previous,
+                    Some((previous, _)) if previous != TokenType::Comment
+                );
+                let is_trailing = after_code && start_line == self.previous_line;
+
+                if is_trailing {
+                    self.line.push(' ');
+                } else {
+                    self.end_line();
+
+                    if after_blank_line {
+                        self.output.push('\n');
+                    }
+
+                    self.start_line();
+                }
+
+                self.line.push_str(token.code.trim_end());
+                self.end_line();
+                self.previous = Some((TokenType::Comment, false));
+                self.previous_line = token.line;
+                return;
+            }
+            _ => {}
+        }
+
+        if after_blank_line && self.previous == Some((TokenType::Comment, false)) {
+            self.output.push('\n');
+        }
+
+        match token.token_type {
+            TokenType::Import => {
+                self.end_line();
+                self.in_import = true;
+            }
+            TokenType::Semicolon => {
+                self.line.push_str(token.code);
+                self.previous = Some((TokenType::Semicolon, false));
+                self.previous_line = token.line;
+                self.in_import = false;
+                return;
+            }
+            _ if self.previous == Some((TokenType::Semicolon, false)) => {
+                self.end_line();
+                self.output.push('\n');
+            }
+            _ => {}
+        }
+
+        let is_unary = token.token_type == TokenType::Bang
+            || (token.token_type == TokenType::Minus && !self.after_operand());
+
+        let needs_space = match self.previous {
+            None
+            | Some((TokenType::Comment, _))
+            | Some((TokenType::LeftParen, _))
+            | Some((TokenType::LeftBracket, _))
+            | Some((TokenType::LeftBrace, _))
+            | Some((TokenType::Dot, _)) => false,
+            Some((_, true)) => false,
+            Some((TokenType::Colon, _)) if self.in_slice() => false,
+            _ => match token.token_type {
+                TokenType::RightParen
+                | TokenType::RightBracket
+                | TokenType::RightBrace
+                | TokenType::Comma
+                | TokenType::Colon
+                | TokenType::Dot => false,
+                TokenType::LeftParen | TokenType::LeftBracket => !self.after_operand(),
+                _ => true,
+            },
+        };
+
+        let after_break_point = matches!(
+            self.previous,
+            Some((previous, false))
+                if previous == TokenType::Comma
+                    || BinaryOperator::from_token_type(previous).is_some()
+        );
+        let first_line = token.code.lines().next().unwrap_or("");
+
+        if self.line.is_empty() {
+            self.start_line();
+        } else if after_break_point
+            && width(&self.line) + 1 + first_line.chars().count() > MAX_WIDTH
+        {
+            self.end_line();
+            self.start_line();
+        } else if needs_space {
+            self.line.push(' ');
+        }
+
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => {
+                self.delimiters.push(token.token_type)
+            }
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                self.delimiters.pop();
+            }
+            _ => {}
+        }
+
+        self.line.push_str(token.code);
+        self.previous = Some((token.token_type, is_unary));
+        self.previous_line = token.line;
+        self.in_expression = !self.in_import;
+    }
+
+    /// Whether the previous token ends an operand, which makes a `-` after
+    /// it a binary operator.
+    fn after_operand(&self) -> bool {
+        matches!(
+            self.previous,
+            Some((
+                TokenType::Number
+                    | TokenType::String
+                    | TokenType::True
+                    | TokenType::False
+                    | TokenType::Nil
+                    | TokenType::Identifier
+                    | TokenType::RightParen
+                    | TokenType::RightBracket
+                    | TokenType::RightBrace,
+                _
+            ))
+        )
+    }
+
+    /// Whether a colon would be the one of a slice rather than a map entry.
+    fn in_slice(&self) -> bool {
+        self.delimiters.last() == Some(&TokenType::LeftBracket)
+    }
+
+    fn start_line(&mut self) {
+        if self.in_expression {
+            self.line.push_str(CONTINUATION_INDENT);
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.line.is_empty() {
+            self.output.push_str(self.line.trim_end());
+            self.output.push('\n');
+            self.line.clear();
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.end_line();
+        self.output
+    }
+}
+
+/// The width of the last line of some text, which can span several lines
+/// because of multi-line strings.
+fn width(text: &str) -> usize {
+    text.rsplit('\n').next().unwrap_or("").chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_formats_to(code: &str, expected: &str) {
+        let formatted = format_code(code).expect("Error formatting code");
+        assert_eq!(formatted, expected);
+        assert_eq!(format_code(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_spacing() {
+        assert_formats_to("1+2*  3", "1 + 2 * 3\n");
+        assert_formats_to("- ( 1 -2 ) >=-3", "-(1 - 2) >= -3\n");
+        assert_formats_to("!  !true!=nil", "!!true != nil\n");
+        assert_formats_to("1 +\n\n  2", "1 + 2\n");
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_formats_to("max ( 1 ,2 )+f ()( 3)", "max(1, 2) + f()(3)\n");
+        assert_formats_to("-sqrt(-(1))", "-sqrt(-(1))\n");
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_formats_to(
+            "[ 1,[ ] ,- 2 ] [ -1 ]=x . len ( )",
+            "[1, [], -2][-1] = x.len()\n",
+        );
+        assert_formats_to("f()[0] - [1]", "f()[0] - [1]\n");
+    }
+
+    #[test]
+    fn test_maps() {
+        assert_formats_to(
+            "{ \"a\" :1,2:{ } } [ nil ]={-1:x}",
+            "{\"a\": 1, 2: {}}[nil] = {-1: x}\n",
+        );
+        assert_formats_to("{}[0] - {}", "{}[0] - {}\n");
+    }
+
+    #[test]
+    fn test_slices() {
+        assert_formats_to(
+            "s [ 1 : - 1 ]+s[ : ]+ s[ f ( ) :]",
+            "s[1:-1] + s[:] + s[f():]\n",
+        );
+        assert_formats_to("[{1: s[:2]}]", "[{1: s[:2]}]\n");
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_formats_to(
+            "// The answer\n\n\n1 +   // one\n  // and then\n2 // two  ",
+            "// The answer\n\n1 + // one\n    // and then\n    2 // two\n",
+        );
+        assert_formats_to("1 // done\n// end", "1 // done\n// end\n");
+    }
+
+    #[test]
+    fn test_imports() {
+        assert_formats_to(
+            "import   \"a.lox\" ;import \"b.lox\";1+2",
+            "import \"a.lox\";\nimport \"b.lox\";\n\n1 + 2\n",
+        );
+        assert_formats_to(
+            "// Helpers\nimport \"a.lox\"; // first\n\n// Code\n1",
+            "// Helpers\nimport \"a.lox\"; // first\n\n// Code\n1\n",
+        );
+    }
+
+    #[test]
+    fn test_wrapping() {
+        let code = ["123456789"; 10].join(" + ");
+        let formatted = format_code(&code).unwrap();
+
+        assert!(formatted
+            .lines()
+            .all(|line| line.chars().count() <= MAX_WIDTH));
+        assert_eq!(formatted.lines().count(), 2);
+        assert!(formatted
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("    123456789"));
+        assert_eq!(format_code(&formatted).unwrap(), formatted);
+
+        let code = format!("max({})", ["123456789"; 10].join(", "));
+        let formatted = format_code(&code).unwrap();
+        assert_eq!(formatted.lines().count(), 2);
+        assert!(formatted.lines().next().unwrap().ends_with(','));
+    }
+
+    #[test]
+    fn test_invalid_code_is_not_formatted() {
+        assert!(format_code("(1 + 2").is_err());
+    }
+}
@@ -1,4 +1,5 @@
-#[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Instruction {
     OpReturn,
     OpConstant(usize),
@@ -12,6 +13,129 @@ pub enum Instruction {
     OpFalse,
     OpNot,
     OpEqual,
+    OpNotEqual,
     OpGreater,
+    OpGreaterEqual,
     OpLess,
+    OpLessEqual,
+    /// Loads the global named by a string constant.
+    OpGetGlobal(usize),
+    /// Calls the value below the given number of arguments on the stack.
+    OpCall(usize),
+    /// Replaces the given number of values on the stack with a list of them.
+    OpBuildList(usize),
+    OpGetIndex,
+    OpSetIndex,
+    /// Replaces the value on the stack with its method named by a string
+    /// constant.
+    OpGetProperty(usize),
+    /// Replaces the given number of key and value pairs on the stack with a
+    /// map of them.
+    OpBuildMap(usize),
+    /// Replaces a list or a string and the two bounds above it with the
+    /// slice between the bounds.
+    OpSlice,
+
+    // Superinstructions, which fuse an `OpConstant` with the binary operation
+    // that follows it. Only the optimizer emits these.
+    OpAddConstant(usize),
+    OpSubtractConstant(usize),
+    OpMultiplyConstant(usize),
+    OpDivideConstant(usize),
+}
+
+impl Instruction {
+    /// The index of the constant this instruction refers to, if any.
+    pub fn constant_operand(&self) -> Option<usize> {
+        match self {
+            Instruction::OpConstant(index)
+            | Instruction::OpGetGlobal(index)
+            | Instruction::OpGetProperty(index)
+            | Instruction::OpAddConstant(index)
+            | Instruction::OpSubtractConstant(index)
+            | Instruction::OpMultiplyConstant(index)
+            | Instruction::OpDivideConstant(index) => Some(*index),
+            _ => None,
+        }
+    }
+
+    /// The number of values this instruction takes from the stack, for the
+    /// instructions where it varies.
+    pub fn count_operand(&self) -> Option<usize> {
+        match self {
+            Instruction::OpCall(count)
+            | Instruction::OpBuildList(count)
+            | Instruction::OpBuildMap(count) => Some(*count),
+            _ => None,
+        }
+    }
+
+    /// How many values this instruction pops off the stack and how many it
+    /// pushes back.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Instruction::OpReturn => (1, 0),
+            Instruction::OpConstant(_)
+            | Instruction::OpNil
+            | Instruction::OpTrue
+            | Instruction::OpFalse
+            | Instruction::OpGetGlobal(_) => (0, 1),
+            Instruction::OpNegate
+            | Instruction::OpNot
+            | Instruction::OpGetProperty(_)
+            | Instruction::OpAddConstant(_)
+            | Instruction::OpSubtractConstant(_)
+            | Instruction::OpMultiplyConstant(_)
+            | Instruction::OpDivideConstant(_) => (1, 1),
+            Instruction::OpAdd
+            | Instruction::OpSubtract
+            | Instruction::OpMultiply
+            | Instruction::OpDivide
+            | Instruction::OpEqual
+            | Instruction::OpNotEqual
+            | Instruction::OpGreater
+            | Instruction::OpGreaterEqual
+            | Instruction::OpLess
+            | Instruction::OpLessEqual
+            | Instruction::OpGetIndex => (2, 1),
+            Instruction::OpSetIndex | Instruction::OpSlice => (3, 1),
+            Instruction::OpCall(arguments_count) => (arguments_count + 1, 1),
+            Instruction::OpBuildList(elements_count) => (*elements_count, 1),
+            Instruction::OpBuildMap(entries_count) => (2 * entries_count, 1),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::OpReturn => "OpReturn",
+            Instruction::OpConstant(_) => "OpConstant",
+            Instruction::OpNegate => "OpNegate",
+            Instruction::OpAdd => "OpAdd",
+            Instruction::OpSubtract => "OpSubtract",
+            Instruction::OpMultiply => "OpMultiply",
+            Instruction::OpDivide => "OpDivide",
+            Instruction::OpNil => "OpNil",
+            Instruction::OpTrue => "OpTrue",
+            Instruction::OpFalse => "OpFalse",
+            Instruction::OpNot => "OpNot",
+            Instruction::OpEqual => "OpEqual",
+            Instruction::OpNotEqual => "OpNotEqual",
+            Instruction::OpGreater => "OpGreater",
+            Instruction::OpGreaterEqual => "OpGreaterEqual",
+            Instruction::OpLess => "OpLess",
+            Instruction::OpLessEqual => "OpLessEqual",
+            Instruction::OpGetGlobal(_) => "OpGetGlobal",
+            Instruction::OpCall(_) => "OpCall",
+            Instruction::OpBuildList(_) => "OpBuildList",
+            Instruction::OpGetIndex => "OpGetIndex",
+            Instruction::OpSetIndex => "OpSetIndex",
+            Instruction::OpGetProperty(_) => "OpGetProperty",
+            Instruction::OpBuildMap(_) => "OpBuildMap",
+            Instruction::OpSlice => "OpSlice",
+            Instruction::OpAddConstant(_) => "OpAddConstant",
+            Instruction::OpSubtractConstant(_) => "OpSubtractConstant",
+            Instruction::OpMultiplyConstant(_) => "OpMultiplyConstant",
+            Instruction::OpDivideConstant(_) => "OpDivideConstant",
+        }
+    }
 }
@@ -0,0 +1,646 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::disassembler::instruction_records;
+use crate::rlox::value::{ObjValue, Value};
+use std::fmt;
+use std::fmt::Write;
+
+/// Dumps a chunk as JSON, for tools that want to consume compiler output.
+///
+/// The JSON is built as a `Json` document, so that the crate doesn't need to
+/// pull in a serializer. Instructions are listed in order with their operands, the line
+/// they came from and, for instructions that reference the constant pool, the
+/// constant they resolve to.
+pub fn chunk_to_json(chunk: &Chunk, name: &str) -> String {
+    let instructions = instruction_records(chunk)
+        .map(|record| {
+            let mut members = vec![
+                ("offset", Json::from(record.offset)),
+                ("line", Json::from(record.line)),
+                ("op", Json::from(record.name)),
+                (
+                    "operands",
+                    Json::Array(record.operands.iter().map(|o| Json::from(*o)).collect()),
+                ),
+            ];
+
+            if let Some(constant) = record.constant {
+                members.push(("constant", json_value(constant)));
+            }
+
+            Json::object(members)
+        })
+        .collect();
+
+    let json = Json::object(vec![
+        ("name", Json::from(name)),
+        ("instructions", Json::Array(instructions)),
+        (
+            "constants",
+            Json::Array(chunk.constants().map(json_value).collect()),
+        ),
+    ]);
+
+    // One line for each member of the dump and for each instruction and
+    // constant, which keeps it readable and easy to diff.
+    let mut out = String::new();
+    write_expanded(&mut out, &json, 2, 0);
+    out.push('\n');
+    out
+}
+
+fn json_value(value: &Value) -> Json {
+    let json = match value {
+        Value::Boolean(boolean) => Json::Boolean(*boolean),
+        // JSON has no representation for NaN or the infinities, those are
+        // written as the strings Lox would display them as.
+        Value::Number(number) if !number.is_finite() => Json::from(value.to_string()),
+        Value::Number(number) => Json::Number(*number),
+        Value::Nil => Json::Null,
+        Value::Obj(obj) => match &obj.value {
+            ObjValue::String(string) => Json::from(string.as_str()),
+            _ => Json::from(value.to_string()),
+        },
+    };
+
+    Json::object(vec![
+        ("type", Json::from(value.type_name())),
+        ("value", json),
+    ])
+}
+
+/// Writes the arrays and objects of the first `levels` levels with one
+/// element per line, indented, and everything deeper on a single line, with
+/// spaces after commas and colons.
+fn write_expanded(out: &mut String, json: &Json, levels: usize, indent: usize) {
+    let inner = " ".repeat(indent + 2);
+
+    match json {
+        Json::Array(elements) if levels > 0 && !elements.is_empty() => {
+            out.push('[');
+
+            for (i, element) in elements.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                out.push_str(&inner);
+                write_expanded(out, element, levels - 1, indent + 2);
+            }
+
+            write!(out, "\n{}]", " ".repeat(indent)).unwrap();
+        }
+        Json::Object(members) if levels > 0 && !members.is_empty() => {
+            out.push('{');
+
+            for (i, (key, value)) in members.iter().enumerate() {
+                out.push_str(if i == 0 { "\n" } else { ",\n" });
+                write!(out, "{}{}: ", inner, json_string(key)).unwrap();
+                write_expanded(out, value, levels - 1, indent + 2);
+            }
+
+            write!(out, "\n{}}}", " ".repeat(indent)).unwrap();
+        }
+        json => write!(out, "{:#}", json).unwrap(),
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut out = String::with_capacity(string.len() + 2);
+    out.push('"');
+
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// A JSON document, for the tools that need to read JSON as well as write
+/// it. Objects keep their keys in order.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object out of its members.
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (String::from(key), value))
+                .collect(),
+        )
+    }
+
+    /// Looks up a member of an object, returning `None` for anything else.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(member, _)| member == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Follows a path of object keys, e.g. `["position", "line"]`.
+    pub fn pointer(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, key| json.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(number) if *number >= 0.0 && number.fract() == 0.0 => {
+                Some(*number as usize)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(elements) => Some(elements),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(string: &str) -> Json {
+        Json::String(String::from(string))
+    }
+}
+
+impl From<String> for Json {
+    fn from(string: String) -> Json {
+        Json::String(string)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(number: usize) -> Json {
+        Json::Number(number as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(boolean: bool) -> Json {
+        Json::Boolean(boolean)
+    }
+}
+
+/// Writes the JSON compactly, on a single line. The alternate form, `{:#}`,
+/// adds a space after commas and colons.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (comma, colon) = if f.alternate() {
+            (", ", ": ")
+        } else {
+            (",", ":")
+        };
+
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Boolean(boolean) => write!(f, "{}", boolean),
+            Json::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(string) => write!(f, "{}", json_string(string)),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "{}", comma)?;
+                    }
+
+                    element.fmt(f)?;
+                }
+
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "{}", comma)?;
+                    }
+
+                    write!(f, "{}{}", json_string(key), colon)?;
+                    value.fmt(f)?;
+                }
+
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// How deeply arrays and objects can be nested in a document that's parsed.
+/// The parser is recursive, so this keeps it from overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Parses a JSON document, returning a description of the problem if it's
+/// not valid.
+pub fn parse_json(text: &str) -> Result<Json, String> {
+    let mut parser = JsonParser {
+        chars: text.chars().collect(),
+        position: 0,
+        depth: 0,
+    };
+
+    let json = parser.value()?;
+    parser.skip_whitespace();
+
+    if parser.position < parser.chars.len() {
+        return Err(format!("Unexpected data at {}", parser.position));
+    }
+
+    Ok(json)
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    position: usize,
+    /// The number of arrays and objects the parser is in.
+    depth: usize,
+}
+
+impl JsonParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.peek().ok_or("Unexpected end of JSON")?;
+        self.position += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next()? {
+            c if c == expected => Ok(()),
+            c => Err(format!(
+                "Expected '{}' but found '{}' at {}",
+                expected,
+                c,
+                self.position - 1
+            )),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, json: Json) -> Result<Json, String> {
+        for expected in keyword.chars() {
+            self.expect(expected)?;
+        }
+
+        Ok(json)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+
+        match self.peek().ok_or("Unexpected end of JSON")? {
+            'n' => self.keyword("null", Json::Null),
+            't' => self.keyword("true", Json::Boolean(true)),
+            'f' => self.keyword("false", Json::Boolean(false)),
+            '"' => self.string().map(Json::String),
+            '[' | '{' if self.depth == MAX_DEPTH => Err(format!(
+                "Too deeply nested at {}, the limit is {}",
+                self.position, MAX_DEPTH
+            )),
+            '[' => self.nested(Self::array),
+            '{' => self.nested(Self::object),
+            '-' | '0'..='9' => self.number(),
+            c => Err(format!("Unexpected '{}' at {}", c, self.position)),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, String>) -> Result<Json, String> {
+        self.depth += 1;
+        let json = parse(self);
+        self.depth -= 1;
+        json
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+
+        while matches!(self.peek(), Some('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+            self.position += 1;
+        }
+
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| format!("Invalid number '{}' at {}", text, start))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+
+        loop {
+            match self.next()? {
+                '"' => return Ok(string),
+                '\\' => match self.next()? {
+                    '"' => string.push('"'),
+                    '\\' => string.push('\\'),
+                    '/' => string.push('/'),
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    'u' => {
+                        let high = self.hex_escape()?;
+
+                        // Characters outside the basic plane are written as
+                        // a surrogate pair of escapes.
+                        let code = if (0xd800..0xdc00).contains(&high) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex_escape()?;
+
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(format!(
+                                    "Expected a low surrogate after '\\u{:04x}' but found '\\u{:04x}'",
+                                    high, low
+                                ));
+                            }
+
+                            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                        } else if (0xdc00..0xe000).contains(&high) {
+                            return Err(format!("Unpaired low surrogate '\\u{:04x}'", high));
+                        } else {
+                            high
+                        };
+
+                        string.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    c => return Err(format!("Invalid escape '\\{}'", c)),
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+
+        for _ in 0..4 {
+            let digit = self.next()?.to_digit(16).ok_or("Invalid unicode escape")?;
+            code = code * 16 + digit;
+        }
+
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut elements = vec![];
+        self.skip_whitespace();
+
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(elements));
+        }
+
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+
+            match self.next()? {
+                ',' => continue,
+                ']' => return Ok(Json::Array(elements)),
+                c => return Err(format!("Expected ',' or ']' but found '{}'", c)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = vec![];
+        self.skip_whitespace();
+
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+
+            match self.next()? {
+                ',' => continue,
+                '}' => return Ok(Json::Object(members)),
+                c => return Err(format!("Expected ',' or '}}' but found '{}'", c)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::compiler::compile;
+    use crate::rlox::instruction::Instruction;
+
+    #[test]
+    fn test_chunk_to_json() {
+        let mut chunk = Chunk::new();
+        let constant_index = chunk.add_constant(Value::from("say \"hi\"\n"));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(Instruction::OpReturn, 2);
+
+        assert_eq!(
+            chunk_to_json(&chunk, "script"),
+            r#"{
+  "name": "script",
+  "instructions": [
+    {"offset": 0, "line": 1, "op": "OpConstant", "operands": [0], "constant": {"type": "string", "value": "say \"hi\"\n"}},
+    {"offset": 1, "line": 2, "op": "OpReturn", "operands": []}
+  ],
+  "constants": [
+    {"type": "string", "value": "say \"hi\"\n"}
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_dump_compiled_code() {
+        let mut chunk = Chunk::new();
+        compile(
+            "-x.f(nil, true, [\"tab\\t\", 0.5]) + 123456789012345678901234 / {0: \"é\"}[0]",
+            &mut chunk,
+        )
+        .expect("Error compiling code");
+        chunk.add_constant(Value::Number(f64::INFINITY));
+
+        assert_eq!(
+            chunk_to_json(&chunk, "dump \"me\""),
+            r#"{
+  "name": "dump \"me\"",
+  "instructions": [
+    {"offset": 0, "line": 1, "op": "OpGetGlobal", "operands": [0], "constant": {"type": "string", "value": "x"}},
+    {"offset": 1, "line": 1, "op": "OpGetProperty", "operands": [1], "constant": {"type": "string", "value": "f"}},
+    {"offset": 2, "line": 1, "op": "OpNil", "operands": []},
+    {"offset": 3, "line": 1, "op": "OpTrue", "operands": []},
+    {"offset": 4, "line": 1, "op": "OpConstant", "operands": [2], "constant": {"type": "string", "value": "tab\\t"}},
+    {"offset": 5, "line": 1, "op": "OpConstant", "operands": [3], "constant": {"type": "number", "value": 0.5}},
+    {"offset": 6, "line": 1, "op": "OpBuildList", "operands": [2]},
+    {"offset": 7, "line": 1, "op": "OpCall", "operands": [3]},
+    {"offset": 8, "line": 1, "op": "OpNegate", "operands": []},
+    {"offset": 9, "line": 1, "op": "OpConstant", "operands": [4], "constant": {"type": "number", "value": 123456789012345690000000}},
+    {"offset": 10, "line": 1, "op": "OpConstant", "operands": [5], "constant": {"type": "number", "value": 0}},
+    {"offset": 11, "line": 1, "op": "OpConstant", "operands": [6], "constant": {"type": "string", "value": "é"}},
+    {"offset": 12, "line": 1, "op": "OpBuildMap", "operands": [1]},
+    {"offset": 13, "line": 1, "op": "OpConstant", "operands": [7], "constant": {"type": "number", "value": 0}},
+    {"offset": 14, "line": 1, "op": "OpGetIndex", "operands": []},
+    {"offset": 15, "line": 1, "op": "OpDivide", "operands": []},
+    {"offset": 16, "line": 1, "op": "OpAdd", "operands": []},
+    {"offset": 17, "line": 1, "op": "OpReturn", "operands": []}
+  ],
+  "constants": [
+    {"type": "string", "value": "x"},
+    {"type": "string", "value": "f"},
+    {"type": "string", "value": "tab\\t"},
+    {"type": "number", "value": 0.5},
+    {"type": "number", "value": 123456789012345690000000},
+    {"type": "number", "value": 0},
+    {"type": "string", "value": "é"},
+    {"type": "number", "value": 0},
+    {"type": "number", "value": "inf"}
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_non_finite_numbers() {
+        assert_eq!(
+            json_value(&Value::Number(f64::NAN)).to_string(),
+            r#"{"type":"number","value":"nan"}"#
+        );
+        assert_eq!(
+            json_value(&Value::Number(2.5)).to_string(),
+            r#"{"type":"number","value":2.5}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let json = parse_json(
+            r#" {"id": 1, "params": {"text": "a\"b\u00e9\ud83d\ude00", "list": [true, null, -2.5e1]}} "#,
+        )
+        .unwrap();
+
+        assert_eq!(json.get("id").and_then(Json::as_usize), Some(1));
+        assert_eq!(
+            json.pointer(&["params", "text"]).and_then(Json::as_str),
+            Some("a\"bé😀")
+        );
+        assert_eq!(
+            json.pointer(&["params", "list"]),
+            Some(&Json::Array(vec![
+                Json::Boolean(true),
+                Json::Null,
+                Json::Number(-25.0)
+            ]))
+        );
+
+        assert!(parse_json("{\"a\": }").is_err());
+        assert!(parse_json("[1, 2").is_err());
+        assert!(parse_json("1 2").is_err());
+    }
+
+    #[test]
+    fn test_invalid_surrogates() {
+        assert_eq!(
+            parse_json(r#""\ud800\u0041""#),
+            Err(String::from(
+                "Expected a low surrogate after '\\ud800' but found '\\u0041'"
+            ))
+        );
+        assert_eq!(
+            parse_json(r#""\udc00""#),
+            Err(String::from("Unpaired low surrogate '\\udc00'"))
+        );
+        assert!(parse_json(r#""\ud800""#).is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+        assert!(parse_json(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            parse_json(&nested(MAX_DEPTH + 1)),
+            Err(format!(
+                "Too deeply nested at {}, the limit is 128",
+                MAX_DEPTH
+            ))
+        );
+        assert!(parse_json(&"{\"a\":".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn test_write_json() {
+        let json = Json::object(vec![
+            ("name", Json::from("say \"hi\"")),
+            ("items", Json::Array(vec![Json::from(1), Json::Null])),
+            ("empty", Json::Object(vec![])),
+        ]);
+
+        assert_eq!(
+            json.to_string(),
+            r#"{"name":"say \"hi\"","items":[1,null],"empty":{}}"#
+        );
+        assert_eq!(parse_json(&json.to_string()), Ok(json.clone()));
+        assert_eq!(
+            format!("{:#}", json),
+            r#"{"name": "say \"hi\"", "items": [1, null], "empty": {}}"#
+        );
+    }
+
+    #[test]
+    fn test_empty_chunk() {
+        assert_eq!(
+            chunk_to_json(&Chunk::new(), "empty"),
+            "{\n  \"name\": \"empty\",\n  \"instructions\": [],\n  \"constants\": []\n}\n"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use crate::rlox::ast::{BinaryOperator, Expr};
+use crate::rlox::diagnostic::Diagnostic;
+use crate::rlox::parser::parse;
+
+/// Looks for code that's valid but most likely a mistake. The code has to
+/// parse first, so a syntax error is the only diagnostic when there's one.
+pub fn lint(code: &str) -> Vec<Diagnostic> {
+    let program = match parse(code) {
+        Ok(program) => program,
+        Err(error) => return vec![error],
+    };
+
+    let mut warnings = vec![];
+    check(&program.expression, &mut warnings);
+    warnings.sort_by_key(|warning| (warning.line, warning.column));
+    warnings
+}
+
+fn check(expression: &Expr, warnings: &mut Vec<Diagnostic>) {
+    match expression {
+        Expr::Literal { .. } | Expr::Variable { .. } => {}
+        Expr::Get { object, .. } => check(object, warnings),
+        Expr::List { elements, .. } => {
+            for element in elements {
+                check(element, warnings);
+            }
+        }
+        Expr::Map { entries, .. } => {
+            for (key, value) in entries {
+                check(key, warnings);
+                check(value, warnings);
+            }
+        }
+        Expr::Index { object, index, .. } => {
+            check(object, warnings);
+            check(index, warnings);
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            check(object, warnings);
+            check(start, warnings);
+            check(end, warnings);
+        }
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            check(object, warnings);
+            check(index, warnings);
+            check(value, warnings);
+        }
+        Expr::Grouping { expression, .. } => check(expression, warnings),
+        Expr::Unary { operand, .. } => check(operand, warnings),
+        Expr::Binary {
+            left,
+            operator,
+            right,
+            line,
+            column,
+        } => {
+            // Without parentheses, `x == nil == false` reads like it checks
+            // both comparisons, but it compares the result of the first one.
+            if let Expr::Binary {
+                operator: inner, ..
+            } = left.as_ref()
+            {
+                if is_comparison(operator) && is_comparison(inner) {
+                    warnings.push(Diagnostic::warning(
+                        *line,
+                        *column,
+                        format!(
+                            "Chained comparison: '{}' compares the result of '{}', \
+                             add parentheses if that's intended",
+                            operator.symbol(),
+                            inner.symbol()
+                        ),
+                    ));
+                }
+            }
+
+            check(left, warnings);
+            check(right, warnings);
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            check(callee, warnings);
+
+            for argument in arguments {
+                check(argument, warnings);
+            }
+        }
+    }
+}
+
+fn is_comparison(operator: &BinaryOperator) -> bool {
+    use BinaryOperator::*;
+
+    matches!(
+        operator,
+        Equal | NotEqual | Greater | GreaterEqual | Less | LessEqual
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_to_strings(code: &str) -> Vec<String> {
+        lint(code).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_chained_comparisons() {
+        assert_eq!(
+            lint_to_strings("1 == nil == false"),
+            [
+                "[line 1:10] Warning: Chained comparison: '==' compares the result of '==', \
+              add parentheses if that's intended"
+            ]
+        );
+        assert_eq!(lint_to_strings("1 < 2 < 3 !=\n4").len(), 2);
+    }
+
+    #[test]
+    fn test_clean_code() {
+        assert!(lint("(1 == nil) == false").is_empty());
+        assert!(lint("1 + 2 == 3").is_empty());
+        assert!(lint("!(1 < 2)").is_empty());
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert_eq!(
+            lint_to_strings("1 +"),
+            ["[line 1:4] Error: Expected prefix expression"]
+        );
+    }
+}
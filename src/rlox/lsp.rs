@@ -0,0 +1,611 @@
+use crate::rlox::diagnostic::{Diagnostic, Severity};
+use crate::rlox::json::{parse_json, Json};
+use crate::rlox::linter::lint;
+use crate::rlox::scanner::Scanner;
+use crate::rlox::token::{Token, TokenType};
+use crate::rlox::vm::Vm;
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, Read, Write};
+
+const KEYWORDS: [&str; 17] = [
+    "and", "class", "else", "false", "for", "fun", "if", "import", "nil", "or", "print", "return",
+    "super", "this", "true", "var", "while",
+];
+
+// JSON-RPC error codes.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+/// The largest message the server accepts, so that a client can't make it
+/// allocate any amount of memory.
+const MAX_MESSAGE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Serves the Language Server Protocol over a pair of streams, usually stdin
+/// and stdout, until the client sends `exit`. Returns the exit code the
+/// protocol asks for: 0 if the client asked for a shutdown first, 1
+/// otherwise.
+///
+/// Documents are synchronized in full on every change, and get diagnostics
+/// from the linter each time.
+pub fn run_language_server(input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<i32> {
+    let mut server = LanguageServer::default();
+
+    loop {
+        let message = match read_message(input)? {
+            Some(message) => message,
+            None => return Ok(1),
+        };
+
+        let replies = match message.and_then(|message| parse_json(&message)) {
+            Ok(message) => server.handle(&message),
+            Err(error) => vec![error_response(Json::Null, PARSE_ERROR, &error)],
+        };
+
+        for reply in replies {
+            write_message(output, &reply)?;
+        }
+
+        if server.exited {
+            return Ok(if server.shutdown { 0 } else { 1 });
+        }
+    }
+}
+
+/// Reads a message framed by a `Content-Length` header. Returns `None` once
+/// the input is over, and the problem with the message when it can't be
+/// read, which the server reports to the client without stopping. Reading
+/// only fails when the input itself does.
+fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Result<String, String>>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = match length {
+        Some(length) => length,
+        None => {
+            return Ok(Some(Err(String::from(
+                "Missing or invalid Content-Length header",
+            ))))
+        }
+    };
+
+    if length > MAX_MESSAGE_LENGTH {
+        // Skip the body so that the next message can still be read.
+        io::copy(&mut Read::take(input, length as u64), &mut io::sink())?;
+
+        return Ok(Some(Err(format!(
+            "Message of {} bytes is longer than the limit of {} bytes",
+            length, MAX_MESSAGE_LENGTH
+        ))));
+    }
+
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(
+        String::from_utf8(body).map_err(|error| format!("Invalid message: {}", error)),
+    ))
+}
+
+fn write_message(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[derive(Default)]
+struct LanguageServer {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exited: bool,
+}
+
+impl LanguageServer {
+    /// Handles a request or a notification, returning the messages to send
+    /// back.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str);
+        let params = message.get("params").unwrap_or(&Json::Null);
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method.unwrap_or(""), params),
+        };
+
+        let result = match method {
+            Some("initialize") => Ok(capabilities()),
+            Some("shutdown") => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            Some("textDocument/hover") => Ok(self.hover(params)),
+            // There are no declarations in the language yet, so nothing has
+            // a definition to go to and documents have no symbols.
+            Some("textDocument/definition") => Ok(Json::Null),
+            Some("textDocument/documentSymbol") => Ok(Json::Array(vec![])),
+            Some("textDocument/completion") => Ok(completions()),
+            Some(method) => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+            None => Err((INVALID_REQUEST, String::from("Missing method"))),
+        };
+
+        let response = match result {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", Json::from("2.0")),
+                ("id", id),
+                ("result", result),
+            ]),
+            Err((code, message)) => error_response(id, code, &message),
+        };
+
+        vec![response]
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params
+            .pointer(&["textDocument", "uri"])
+            .and_then(Json::as_str)
+            .map(String::from);
+
+        match (method, uri) {
+            ("exit", _) => {
+                self.exited = true;
+                vec![]
+            }
+            ("textDocument/didOpen", Some(uri)) => {
+                match params
+                    .pointer(&["textDocument", "text"])
+                    .and_then(Json::as_str)
+                {
+                    Some(text) => self.update(uri, String::from(text)),
+                    None => vec![],
+                }
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // Changes are always full documents, as announced in the
+                // capabilities, so only the last one matters.
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+
+                match text {
+                    Some(text) => self.update(uri, String::from(text)),
+                    None => vec![],
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, "", &[])]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn update(&mut self, uri: String, text: String) -> Vec<Json> {
+        let diagnostics = lint(&text);
+        let notification = publish_diagnostics(&uri, &text, &diagnostics);
+        self.documents.insert(uri, text);
+        vec![notification]
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let text = match params
+            .pointer(&["textDocument", "uri"])
+            .and_then(Json::as_str)
+            .and_then(|uri| self.documents.get(uri))
+        {
+            Some(text) => text,
+            None => return Json::Null,
+        };
+
+        let line = params
+            .pointer(&["position", "line"])
+            .and_then(Json::as_usize);
+        let character = params
+            .pointer(&["position", "character"])
+            .and_then(Json::as_usize);
+
+        let (line, column) = match (line, character) {
+            (Some(line), Some(character)) => from_lsp_position(text, line, character),
+            _ => return Json::Null,
+        };
+
+        let mut scanner = Scanner::new();
+        let token = scanner.scan_with_comments(text).find(|token| {
+            token.start_line() == line
+                && token.column <= column
+                && column < token.column + token.code.chars().count()
+        });
+
+        match token.and_then(|token| describe(&token).map(|kind| (token, kind))) {
+            Some((token, kind)) => Json::object(vec![
+                (
+                    "contents",
+                    Json::object(vec![
+                        ("kind", Json::from("plaintext")),
+                        ("value", Json::from(kind)),
+                    ]),
+                ),
+                (
+                    "range",
+                    range(text, line, token.column, token.code.chars().count()),
+                ),
+            ]),
+            None => Json::Null,
+        }
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                // Full document synchronization.
+                ("textDocumentSync", Json::from(1)),
+                ("hoverProvider", Json::from(true)),
+                ("definitionProvider", Json::from(true)),
+                ("documentSymbolProvider", Json::from(true)),
+                ("completionProvider", Json::Object(vec![])),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![("name", Json::from("rlox"))]),
+        ),
+    ])
+}
+
+fn completions() -> Json {
+    // Completion item kinds from the protocol.
+    const VARIABLE: usize = 6;
+    const KEYWORD: usize = 14;
+
+    let keywords = KEYWORDS
+        .iter()
+        .map(|keyword| (String::from(*keyword), KEYWORD));
+
+    let mut globals: Vec<String> = Vm::new().globals().map(|(name, _)| name.clone()).collect();
+    globals.sort();
+    let globals = globals.into_iter().map(|name| (name, VARIABLE));
+
+    Json::Array(
+        keywords
+            .chain(globals)
+            .map(|(label, kind)| {
+                Json::object(vec![
+                    ("label", Json::from(label)),
+                    ("kind", Json::from(kind)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// What to show when hovering over a token.
+fn describe(token: &Token) -> Option<String> {
+    use TokenType::*;
+
+    let description = match token.token_type {
+        Number => "number literal".to_string(),
+        String => "string literal".to_string(),
+        True | False => "boolean literal".to_string(),
+        Nil => "nil literal".to_string(),
+        Identifier => match Vm::new().globals().find(|(name, _)| *name == token.code) {
+            Some((_, value)) => format!("global {}", value.type_name()),
+            None => "identifier".to_string(),
+        },
+        Whitespace | Comment | Error | Eof => return None,
+        _ if KEYWORDS.contains(&token.code) => "keyword".to_string(),
+        _ => "operator".to_string(),
+    };
+
+    Some(description)
+}
+
+fn publish_diagnostics(uri: &str, text: &str, diagnostics: &[Diagnostic]) -> Json {
+    let diagnostics = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let length = diagnostic
+                .token
+                .as_ref()
+                .map_or(0, |token| token.chars().count());
+
+            // Severities from the protocol.
+            let severity = match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+
+            Json::object(vec![
+                (
+                    "range",
+                    range(text, diagnostic.line, diagnostic.column, length),
+                ),
+                ("severity", Json::from(severity)),
+                ("source", Json::from("rlox")),
+                ("message", Json::from(diagnostic.message.as_str())),
+            ])
+        })
+        .collect();
+
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", Json::from(uri)),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: i32, message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::from("2.0")),
+        ("id", id),
+        (
+            "error",
+            Json::object(vec![
+                ("code", Json::Number(code.into())),
+                ("message", Json::from(message)),
+            ]),
+        ),
+    ])
+}
+
+/// The range covering `length` characters from a line and column, both
+/// counted from 1 like in diagnostics.
+fn range(text: &str, line: usize, column: usize, length: usize) -> Json {
+    Json::object(vec![
+        ("start", to_lsp_position(text, line, column)),
+        ("end", to_lsp_position(text, line, column + length)),
+    ])
+}
+
+/// Converts a line and column counted in characters from 1 into a protocol
+/// position, which counts from 0 and measures columns in UTF-16 code units.
+fn to_lsp_position(text: &str, line: usize, column: usize) -> Json {
+    let line_text = text.lines().nth(line - 1).unwrap_or("");
+    let character: usize = line_text
+        .chars()
+        .take(column - 1)
+        .map(char::len_utf16)
+        .sum();
+
+    Json::object(vec![
+        ("line", Json::from(line - 1)),
+        ("character", Json::from(character)),
+    ])
+}
+
+/// The reverse of `to_lsp_position`.
+fn from_lsp_position(text: &str, line: usize, character: usize) -> (usize, usize) {
+    let line_text = text.lines().nth(line).unwrap_or("");
+    let mut units = 0;
+    let mut column = 1;
+
+    for c in line_text.chars() {
+        if units >= character {
+            break;
+        }
+
+        units += c.len_utf16();
+        column += 1;
+    }
+
+    (line + 1, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the server on a transcript of client messages and returns the
+    /// messages it sent back, along with its exit code.
+    fn run_transcript(messages: &[&str]) -> (Vec<Json>, i32) {
+        let mut input = vec![];
+        for message in messages {
+            write!(
+                input,
+                "Content-Length: {}\r\n\r\n{}",
+                message.len(),
+                message
+            )
+            .unwrap();
+        }
+
+        let mut output = vec![];
+        let code = run_language_server(&mut io::Cursor::new(input), &mut output).unwrap();
+
+        let mut output = io::Cursor::new(output);
+        let mut replies = vec![];
+        while let Some(message) = read_message(&mut output).unwrap() {
+            replies.push(parse_json(&message.unwrap()).unwrap());
+        }
+
+        (replies, code)
+    }
+
+    const OPEN: &str = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.lox","languageId":"lox","version":1,"text":"1 == nil == false"}}}"#;
+
+    #[test]
+    fn test_lifecycle() {
+        let (replies, code) = run_transcript(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":"two","method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+
+        assert_eq!(code, 0);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(
+            replies[0].pointer(&["result", "capabilities", "hoverProvider"]),
+            Some(&Json::Boolean(true))
+        );
+        assert_eq!(
+            replies[1].to_string(),
+            r#"{"jsonrpc":"2.0","id":"two","result":null}"#
+        );
+
+        let (_, code) = run_transcript(&[r#"{"jsonrpc":"2.0","method":"exit"}"#]);
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let (replies, _) = run_transcript(&[
+            OPEN,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.lox","version":2},"contentChanges":[{"text":"\"é\" +\n  )"}]}}"#,
+        ]);
+
+        assert_eq!(
+            replies[0].to_string(),
+            r#"{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.lox","diagnostics":[{"range":{"start":{"line":0,"character":9},"end":{"line":0,"character":9}},"severity":2,"source":"rlox","message":"Chained comparison: '==' compares the result of '==', add parentheses if that's intended"}]}}"#
+        );
+        assert_eq!(
+            replies[1]
+                .pointer(&["params", "diagnostics"])
+                .unwrap()
+                .to_string(),
+            r#"[{"range":{"start":{"line":1,"character":2},"end":{"line":1,"character":3}},"severity":1,"source":"rlox","message":"Expected prefix expression"}]"#
+        );
+    }
+
+    #[test]
+    fn test_hover_and_completion() {
+        let (replies, _) = run_transcript(&[
+            OPEN,
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.lox"},"position":{"line":0,"character":6}}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.lox"},"position":{"line":0,"character":1}}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"textDocument/completion","params":{"textDocument":{"uri":"file:///a.lox"},"position":{"line":0,"character":0}}}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.lox"},"position":{"line":0,"character":0}}}"#,
+            r#"{"jsonrpc":"2.0","id":5,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.lox"}}}"#,
+        ]);
+
+        assert_eq!(
+            replies[1].get("result").unwrap().to_string(),
+            r#"{"contents":{"kind":"plaintext","value":"nil literal"},"range":{"start":{"line":0,"character":5},"end":{"line":0,"character":8}}}"#
+        );
+        assert_eq!(replies[2].get("result"), Some(&Json::Null));
+
+        let labels: Vec<&str> = replies[3]
+            .get("result")
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter()
+            .filter_map(|item| item.get("label").and_then(Json::as_str))
+            .collect();
+        assert!(labels.contains(&"while"));
+
+        assert_eq!(replies[4].get("result"), Some(&Json::Null));
+        assert_eq!(replies[5].get("result"), Some(&Json::Array(vec![])));
+    }
+
+    #[test]
+    fn test_errors() {
+        let (replies, _) = run_transcript(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"workspace/unknown"}"#,
+            r#"{"jsonrpc":"2.0","id":2,"#,
+        ]);
+
+        assert_eq!(
+            replies[0].pointer(&["error", "code"]),
+            Some(&Json::Number(-32601.0))
+        );
+        assert_eq!(
+            replies[1].pointer(&["error", "code"]),
+            Some(&Json::Number(-32700.0))
+        );
+    }
+
+    #[test]
+    fn test_malformed_messages_are_answered() {
+        let deep = "[".repeat(100_000);
+
+        let mut input = vec![];
+        input.extend_from_slice(b"Content-Type: text/plain\r\n\r\n");
+        input.extend_from_slice(b"Content-Length: x\r\n\r\n");
+        write!(input, "Content-Length: {}\r\n\r\n{}", deep.len(), deep).unwrap();
+        input.extend_from_slice(b"Content-Length: 2\r\n\r\n\xff\xfe");
+        // The body of a message over the limit is skipped, up to the end of
+        // the input here.
+        write!(
+            input,
+            "Content-Length: {}\r\n\r\n[]",
+            MAX_MESSAGE_LENGTH + 1
+        )
+        .unwrap();
+
+        let mut output = vec![];
+        let code = run_language_server(&mut io::Cursor::new(input), &mut output).unwrap();
+        assert_eq!(code, 1);
+
+        let mut output = io::Cursor::new(output);
+        let mut codes = vec![];
+        while let Some(message) = read_message(&mut output).unwrap() {
+            let reply = parse_json(&message.unwrap()).unwrap();
+            codes.push(reply.pointer(&["error", "code"]).cloned());
+        }
+
+        assert_eq!(codes, vec![Some(Json::Number(PARSE_ERROR as f64)); 5]);
+    }
+
+    #[test]
+    fn test_deeply_nested_documents_are_diagnosed() {
+        let open = OPEN.replace(
+            "1 == nil == false",
+            &format!("{}1{}", "(".repeat(50_000), ")".repeat(50_000)),
+        );
+        let (replies, code) = run_transcript(&[
+            &open,
+            r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+
+        assert_eq!(code, 0);
+        assert_eq!(
+            replies[0]
+                .pointer(&["params", "diagnostics"])
+                .unwrap()
+                .to_string(),
+            r#"[{"range":{"start":{"line":0,"character":128},"end":{"line":0,"character":129}},"severity":1,"source":"rlox","message":"Too deeply nested, the limit is 128"}]"#
+        );
+        assert_eq!(replies[1].pointer(&["result"]), Some(&Json::Null));
+    }
+
+    #[test]
+    fn test_positions_count_utf16_units() {
+        let text = "\"😀\" + 1";
+
+        assert_eq!(
+            to_lsp_position(text, 1, 6).to_string(),
+            r#"{"line":0,"character":6}"#
+        );
+        assert_eq!(from_lsp_position(text, 0, 6), (1, 6));
+    }
+}
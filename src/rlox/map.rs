@@ -0,0 +1,176 @@
+use crate::rlox::value::{Obj, ObjValue, Value};
+use std::collections::HashMap;
+
+/// The keys a map can have: the values that can't change and that are only
+/// equal to values of the same type.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Key {
+    String(String),
+    /// The bits of the number, with `-0` turned into `0` since they're equal.
+    Number(u64),
+    Boolean(bool),
+    Nil,
+}
+
+impl Key {
+    fn new(value: &Value) -> Result<Key, String> {
+        match value {
+            Value::Number(number) if number.is_nan() => {
+                Err(String::from("Can't use nan as a map key"))
+            }
+            Value::Number(number) => Ok(Key::Number((number + 0.0).to_bits())),
+            Value::Boolean(boolean) => Ok(Key::Boolean(*boolean)),
+            Value::Nil => Ok(Key::Nil),
+            Value::Obj(Obj {
+                value: ObjValue::String(string),
+            }) => Ok(Key::String(string.clone())),
+            _ => Err(format!("Can't use a {} as a map key", value.type_name())),
+        }
+    }
+}
+
+/// A hash map that remembers the order its keys were first inserted in, so
+/// that going through it always gives the same order.
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    positions: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, String> {
+        let position = self.positions.get(&Key::new(key)?);
+        Ok(position.map(|position| &self.entries[*position].1))
+    }
+
+    pub fn contains_key(&self, key: &Value) -> Result<bool, String> {
+        Ok(self.positions.contains_key(&Key::new(key)?))
+    }
+
+    /// Sets the value of a key. A key that was already there keeps its place
+    /// in the order.
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), String> {
+        let hash_key = Key::new(&key)?;
+
+        match self.positions.get(&hash_key) {
+            Some(position) => self.entries[*position].1 = value,
+            None => {
+                self.positions.insert(hash_key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes a key and returns its value, if it was there.
+    pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, String> {
+        let position = match self.positions.remove(&Key::new(key)?) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+
+        for later in self.positions.values_mut() {
+            if *later > position {
+                *later -= 1;
+            }
+        }
+
+        Ok(Some(self.entries.remove(position).1))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+/// Maps are equal when they have the same keys with equal values, whatever
+/// the order they were inserted in.
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Ok(Some(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insertion_order() {
+        let mut map = Map::new();
+        map.insert(Value::from("b"), Value::Number(1.0)).unwrap();
+        map.insert(Value::Number(2.0), Value::Nil).unwrap();
+        map.insert(Value::from("a"), Value::Number(3.0)).unwrap();
+        map.insert(Value::from("b"), Value::Number(4.0)).unwrap();
+
+        let keys: Vec<&Value> = map.keys().collect();
+        assert_eq!(
+            keys,
+            [&Value::from("b"), &Value::Number(2.0), &Value::from("a")]
+        );
+        assert_eq!(map.get(&Value::from("b")), Ok(Some(&Value::Number(4.0))));
+
+        assert_eq!(map.remove(&Value::Number(2.0)), Ok(Some(Value::Nil)));
+        assert_eq!(map.remove(&Value::Number(2.0)), Ok(None));
+        assert_eq!(map.get(&Value::from("a")), Ok(Some(&Value::Number(3.0))));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut map = Map::new();
+        map.insert(Value::Number(-0.0), Value::from("zero"))
+            .unwrap();
+        map.insert(Value::Nil, Value::Boolean(true)).unwrap();
+
+        assert_eq!(map.contains_key(&Value::Number(0.0)), Ok(true));
+        assert_eq!(map.contains_key(&Value::from("0")), Ok(false));
+        assert_eq!(map.contains_key(&Value::Boolean(false)), Ok(false));
+        assert_eq!(map.contains_key(&Value::Nil), Ok(true));
+
+        assert_eq!(
+            map.insert(Value::from(vec![]), Value::Nil),
+            Err(String::from("Can't use a list as a map key"))
+        );
+        assert!(map.get(&Value::Number(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_equality_ignores_order() {
+        let mut first = Map::new();
+        first.insert(Value::from("a"), Value::Number(1.0)).unwrap();
+        first.insert(Value::from("b"), Value::Number(2.0)).unwrap();
+
+        let mut second = Map::new();
+        second.insert(Value::from("b"), Value::Number(2.0)).unwrap();
+        second.insert(Value::from("a"), Value::Number(1.0)).unwrap();
+        assert_eq!(first, second);
+
+        second.insert(Value::from("a"), Value::Nil).unwrap();
+        assert_ne!(first, second);
+    }
+}
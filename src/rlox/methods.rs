@@ -0,0 +1,481 @@
+use crate::rlox::convert::FromLox;
+use crate::rlox::map::Map;
+use crate::rlox::stdlib::{arguments, typed};
+use crate::rlox::value::{NativeFunction, Obj, ObjValue, Value};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+type List = Rc<RefCell<Vec<Value>>>;
+type SharedMap = Rc<RefCell<Map>>;
+
+/// Looks up a method of a value. The method comes bound to the value, as a
+/// native function that can be called like any other.
+pub(crate) fn get_method(receiver: &Value, name: &str) -> Result<NativeFunction, String> {
+    let method = match receiver {
+        Value::Obj(Obj {
+            value: ObjValue::List(list),
+        }) => list_method(list, name),
+        Value::Obj(Obj {
+            value: ObjValue::Map(map),
+        }) => map_method(map, name),
+        Value::Obj(Obj {
+            value: ObjValue::String(string),
+        }) => string_method(string, name),
+        _ => None,
+    };
+
+    method.ok_or_else(|| format!("Undefined method '{}' for {}", name, receiver.type_name()))
+}
+
+/// The methods of lists:
+///
+/// - `push(x)` adds an element at the end
+/// - `pop()` removes the last element and returns it
+/// - `insert(i, x)` inserts an element before the one at `i`, or at the end
+///   when `i` is the length of the list
+/// - `remove(i)` removes the element at `i` and returns it
+/// - `len()` returns the number of elements
+/// - `slice(start, end)` returns a new list with the elements from `start`
+///   up to (without) `end`, or up to the end when there's no `end`
+/// - `contains(x)` returns whether an element is equal to `x`
+/// - `reverse()` reverses the list in place
+/// - `sort()` sorts a list of numbers or a list of strings in place
+///
+/// Negative indices count from the end of the list.
+fn list_method(list: &List, name: &str) -> Option<NativeFunction> {
+    let list = Rc::clone(list);
+
+    let method = match name {
+        "push" => NativeFunction::new(
+            name,
+            typed(move |(value,): (Value,)| list.borrow_mut().push(value)),
+        ),
+        "pop" => NativeFunction::new(name, move |arguments| {
+            let () = self::arguments(arguments)?;
+            list.borrow_mut()
+                .pop()
+                .ok_or_else(|| String::from("Can't pop from an empty list"))
+        }),
+        "insert" => NativeFunction::new(name, move |arguments| {
+            let (index, value): (Value, Value) = self::arguments(arguments)?;
+            let mut list = list.borrow_mut();
+            let (index, position) = normalize_index(&index, list.len())?;
+
+            if position < 0 || position > list.len() as i64 {
+                return Err(out_of_bounds(index, list.len()));
+            }
+
+            list.insert(position as usize, value);
+            Ok(Value::Nil)
+        }),
+        "remove" => NativeFunction::new(name, move |arguments| {
+            let (index,): (Value,) = self::arguments(arguments)?;
+            let mut list = list.borrow_mut();
+            let position = resolve_index(&index, list.len())?;
+            Ok(list.remove(position))
+        }),
+        "len" => NativeFunction::new(name, typed(move |()| list.borrow().len())),
+        "slice" => NativeFunction::new(name, move |arguments| {
+            let list = list.borrow();
+            let range = slice_range(arguments, list.len())?;
+            Ok(Value::from(list[range].to_vec()))
+        }),
+        "contains" => NativeFunction::new(
+            name,
+            typed(move |(value,): (Value,)| list.borrow().contains(&value)),
+        ),
+        "reverse" => NativeFunction::new(name, typed(move |()| list.borrow_mut().reverse())),
+        "sort" => NativeFunction::new(name, move |arguments| {
+            let () = self::arguments(arguments)?;
+            let mut list = list.borrow_mut();
+
+            let numbers: Option<Vec<f64>> = list.iter().map(|e| f64::from_lox(e).ok()).collect();
+            if let Some(mut numbers) = numbers {
+                numbers.sort_by(f64::total_cmp);
+                *list = numbers.into_iter().map(Value::Number).collect();
+                return Ok(Value::Nil);
+            }
+
+            let strings: Option<Vec<String>> =
+                list.iter().map(|e| String::from_lox(e).ok()).collect();
+            if let Some(mut strings) = strings {
+                strings.sort();
+                *list = strings.into_iter().map(Value::from).collect();
+                return Ok(Value::Nil);
+            }
+
+            Err(String::from(
+                "Can only sort a list of numbers or a list of strings",
+            ))
+        }),
+        _ => return None,
+    };
+
+    Some(method)
+}
+
+/// The methods of maps:
+///
+/// - `keys()` returns a list of the keys
+/// - `values()` returns a list of the values
+/// - `has(key)` returns whether there's a value for `key`
+/// - `remove(key)` removes a key and returns its value, or nil if there was
+///   none
+/// - `len()` returns the number of keys
+///
+/// Keys and values come in the order the keys were first inserted in.
+fn map_method(map: &SharedMap, name: &str) -> Option<NativeFunction> {
+    let map = Rc::clone(map);
+
+    let method = match name {
+        "keys" => NativeFunction::new(
+            name,
+            typed(move |()| map.borrow().keys().cloned().collect::<Vec<_>>()),
+        ),
+        "values" => NativeFunction::new(
+            name,
+            typed(move |()| map.borrow().values().cloned().collect::<Vec<_>>()),
+        ),
+        "has" => NativeFunction::new(name, move |arguments| {
+            let (key,): (Value,) = self::arguments(arguments)?;
+            map.borrow().contains_key(&key).map(Value::from)
+        }),
+        "remove" => NativeFunction::new(name, move |arguments| {
+            let (key,): (Value,) = self::arguments(arguments)?;
+            let value = map.borrow_mut().remove(&key)?;
+            Ok(value.unwrap_or(Value::Nil))
+        }),
+        "len" => NativeFunction::new(name, typed(move |()| map.borrow().len())),
+        _ => return None,
+    };
+
+    Some(method)
+}
+
+/// The methods of strings, which all return a new string rather than change
+/// the one they're called on:
+///
+/// - `len()` returns the number of characters
+/// - `upper()` and `lower()` change the case of the characters
+/// - `trim()` removes the whitespace at both ends
+/// - `split(separator)` returns the list of the parts between separators, or
+///   of the characters when the separator is empty
+/// - `join(list)` returns the elements of a list as they would be displayed,
+///   with the string between them
+/// - `replace(from, to)` replaces every occurrence of `from` with `to`
+/// - `startsWith(prefix)` and `endsWith(suffix)` check how the string starts
+///   or ends
+/// - `indexOf(part)` returns the index of the first occurrence of `part`, or
+///   -1 if there's none
+/// - `repeat(n)` returns the string repeated `n` times
+///
+/// Like indices, lengths and positions count characters, not bytes.
+fn string_method(string: &str, name: &str) -> Option<NativeFunction> {
+    let string = String::from(string);
+
+    let method = match name {
+        "len" => NativeFunction::new(name, typed(move |()| string.chars().count())),
+        "upper" => NativeFunction::new(name, typed(move |()| string.to_uppercase())),
+        "lower" => NativeFunction::new(name, typed(move |()| string.to_lowercase())),
+        "trim" => NativeFunction::new(name, typed(move |()| string.trim().to_string())),
+        "split" => NativeFunction::new(
+            name,
+            typed(move |(separator,): (String,)| -> Vec<String> {
+                if separator.is_empty() {
+                    string.chars().map(|c| c.to_string()).collect()
+                } else {
+                    string.split(separator.as_str()).map(String::from).collect()
+                }
+            }),
+        ),
+        "join" => NativeFunction::new(
+            name,
+            typed(move |(parts,): (Vec<Value>,)| {
+                let parts: Vec<String> = parts.iter().map(Value::to_string).collect();
+                parts.join(&string)
+            }),
+        ),
+        "replace" => NativeFunction::new(
+            name,
+            typed(move |(from, to): (String, String)| string.replace(&from, &to)),
+        ),
+        "startsWith" => NativeFunction::new(
+            name,
+            typed(move |(prefix,): (String,)| string.starts_with(&prefix)),
+        ),
+        "endsWith" => NativeFunction::new(
+            name,
+            typed(move |(suffix,): (String,)| string.ends_with(&suffix)),
+        ),
+        "indexOf" => NativeFunction::new(
+            name,
+            typed(move |(part,): (String,)| match string.find(&part) {
+                Some(offset) => string[..offset].chars().count() as f64,
+                None => -1.0,
+            }),
+        ),
+        "repeat" => {
+            NativeFunction::new(name, typed(move |(count,): (usize,)| string.repeat(count)))
+        }
+        _ => return None,
+    };
+
+    Some(method)
+}
+
+/// Turns an index into a position in a sequence of `length` elements,
+/// failing if it's outside of it.
+pub(crate) fn resolve_index(index: &Value, length: usize) -> Result<usize, String> {
+    let (index, position) = normalize_index(index, length)?;
+
+    if position < 0 || position >= length as i64 {
+        return Err(out_of_bounds(index, length));
+    }
+
+    Ok(position as usize)
+}
+
+/// Turns the `start` and optional `end` arguments of a slicing method into
+/// the range of positions it covers.
+pub(crate) fn slice_range(arguments: &[Value], length: usize) -> Result<Range<usize>, String> {
+    match arguments {
+        [start] => slice_bounds(start, &Value::Nil, length),
+        [start, end] => slice_bounds(start, end, length),
+        _ => Err(format!(
+            "Expected 1 or 2 arguments but got {}",
+            arguments.len()
+        )),
+    }
+}
+
+/// Turns the bounds of a slice into the range of positions it covers, a nil
+/// bound standing for the start or the end of the sequence. Unlike single
+/// indices, the bounds can go past either end of the sequence, which leaves
+/// out the missing elements.
+pub(crate) fn slice_bounds(
+    start: &Value,
+    end: &Value,
+    length: usize,
+) -> Result<Range<usize>, String> {
+    let clamp = |position: i64| position.max(0).min(length as i64) as usize;
+
+    let start = match start {
+        Value::Nil => 0,
+        start => clamp(normalize_index(start, length)?.1),
+    };
+    let end = match end {
+        Value::Nil => length,
+        end => clamp(normalize_index(end, length)?.1),
+    };
+
+    Ok(start..end.max(start))
+}
+
+/// Checks that an index is an integer and gives the position it stands for,
+/// counting from the end when it's negative. Returns both.
+fn normalize_index(index: &Value, length: usize) -> Result<(i64, i64), String> {
+    let index = i64::from_lox(index).map_err(|error| format!("Invalid index: {}", error))?;
+
+    if index < 0 {
+        Ok((index, index + length as i64))
+    } else {
+        Ok((index, index))
+    }
+}
+
+fn out_of_bounds(index: i64, length: usize) -> String {
+    format!("Index {} is out of bounds for length {}", index, length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(numbers: &[f64]) -> Value {
+        Value::from(
+            numbers
+                .iter()
+                .map(|n| Value::Number(*n))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn call(receiver: &Value, name: &str, arguments: &[Value]) -> Result<Value, String> {
+        get_method(receiver, name)?.call(arguments)
+    }
+
+    #[test]
+    fn test_list_methods() {
+        let list = numbers(&[3.0, 1.0]);
+
+        assert_eq!(call(&list, "push", &[Value::Number(2.0)]), Ok(Value::Nil));
+        assert_eq!(list, numbers(&[3.0, 1.0, 2.0]));
+        assert_eq!(call(&list, "len", &[]), Ok(Value::Number(3.0)));
+
+        call(&list, "insert", &[Value::Number(-1.0), Value::Number(4.0)]).unwrap();
+        call(&list, "insert", &[Value::Number(4.0), Value::Number(5.0)]).unwrap();
+        assert_eq!(list, numbers(&[3.0, 1.0, 4.0, 2.0, 5.0]));
+
+        assert_eq!(call(&list, "pop", &[]), Ok(Value::Number(5.0)));
+        assert_eq!(
+            call(&list, "remove", &[Value::Number(0.0)]),
+            Ok(Value::Number(3.0))
+        );
+        assert_eq!(list, numbers(&[1.0, 4.0, 2.0]));
+
+        assert_eq!(
+            call(&list, "contains", &[Value::Number(4.0)]),
+            Ok(Value::Boolean(true))
+        );
+        assert_eq!(
+            call(&list, "contains", &[Value::from("4")]),
+            Ok(Value::Boolean(false))
+        );
+
+        call(&list, "sort", &[]).unwrap();
+        assert_eq!(list, numbers(&[1.0, 2.0, 4.0]));
+        call(&list, "reverse", &[]).unwrap();
+        assert_eq!(list, numbers(&[4.0, 2.0, 1.0]));
+    }
+
+    #[test]
+    fn test_slice() {
+        let list = numbers(&[1.0, 2.0, 3.0, 4.0]);
+        let slice = |arguments: &[f64]| {
+            let arguments: Vec<Value> = arguments.iter().map(|n| Value::Number(*n)).collect();
+            call(&list, "slice", &arguments)
+        };
+
+        assert_eq!(slice(&[1.0, 3.0]), Ok(numbers(&[2.0, 3.0])));
+        assert_eq!(slice(&[-2.0]), Ok(numbers(&[3.0, 4.0])));
+        assert_eq!(slice(&[-10.0, 10.0]), Ok(numbers(&[1.0, 2.0, 3.0, 4.0])));
+        assert_eq!(slice(&[3.0, 1.0]), Ok(numbers(&[])));
+        assert!(slice(&[]).is_err());
+        assert!(slice(&[0.5]).is_err());
+    }
+
+    #[test]
+    fn test_sort() {
+        let strings = Value::from(vec![Value::from("b"), Value::from("a")]);
+        call(&strings, "sort", &[]).unwrap();
+        assert_eq!(strings.to_string(), "[\"a\", \"b\"]");
+
+        let mixed = Value::from(vec![Value::from("b"), Value::Number(1.0)]);
+        assert!(call(&mixed, "sort", &[]).is_err());
+    }
+
+    #[test]
+    fn test_map_methods() {
+        let mut map = Map::new();
+        map.insert(Value::from("b"), Value::Number(1.0)).unwrap();
+        map.insert(Value::Nil, Value::from("a")).unwrap();
+        let map = Value::from(map);
+
+        assert_eq!(
+            call(&map, "keys", &[]),
+            Ok(Value::from(vec![Value::from("b"), Value::Nil]))
+        );
+        assert_eq!(
+            call(&map, "values", &[]),
+            Ok(Value::from(vec![Value::Number(1.0), Value::from("a")]))
+        );
+        assert_eq!(call(&map, "has", &[Value::Nil]), Ok(Value::Boolean(true)));
+        assert_eq!(
+            call(&map, "has", &[Value::from("a")]),
+            Ok(Value::Boolean(false))
+        );
+
+        assert_eq!(call(&map, "remove", &[Value::Nil]), Ok(Value::from("a")));
+        assert_eq!(call(&map, "remove", &[Value::Nil]), Ok(Value::Nil));
+        assert_eq!(call(&map, "len", &[]), Ok(Value::Number(1.0)));
+
+        assert_eq!(
+            call(&map, "has", &[numbers(&[])]),
+            Err(String::from("Can't use a list as a map key"))
+        );
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let string = Value::from("  Héllo, World  ");
+        let call = |name: &str, arguments: &[&str]| {
+            let arguments: Vec<Value> = arguments.iter().map(|a| Value::from(*a)).collect();
+            call(&string, name, &arguments)
+        };
+
+        assert_eq!(call("len", &[]), Ok(Value::Number(16.0)));
+        assert_eq!(call("upper", &[]), Ok(Value::from("  HÉLLO, WORLD  ")));
+        assert_eq!(call("lower", &[]), Ok(Value::from("  héllo, world  ")));
+        assert_eq!(call("trim", &[]), Ok(Value::from("Héllo, World")));
+        assert_eq!(
+            call("replace", &["l", "L"]),
+            Ok(Value::from("  HéLLo, WorLd  "))
+        );
+        assert_eq!(call("startsWith", &["  H"]), Ok(Value::Boolean(true)));
+        assert_eq!(call("endsWith", &["d"]), Ok(Value::Boolean(false)));
+        assert_eq!(call("indexOf", &["llo"]), Ok(Value::Number(4.0)));
+        assert_eq!(call("indexOf", &["x"]), Ok(Value::Number(-1.0)));
+        assert_eq!(string, Value::from("  Héllo, World  "));
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let split = |string: &str, separator: &str| {
+            call(&Value::from(string), "split", &[Value::from(separator)])
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(split("a,b,,c", ","), "[\"a\", \"b\", \"\", \"c\"]");
+        assert_eq!(split("hé", ""), "[\"h\", \"é\"]");
+        assert_eq!(split("", ","), "[\"\"]");
+
+        let list = Value::from(vec![Value::from("a"), Value::Number(1.0), Value::Nil]);
+        assert_eq!(
+            call(&Value::from(", "), "join", &[list]),
+            Ok(Value::from("a, 1, nil"))
+        );
+        assert_eq!(
+            call(&Value::from(", "), "join", &[Value::from("ab")]),
+            Err(String::from("Argument 1: Expected list but found string"))
+        );
+
+        let repeat = |count: f64| call(&Value::from("ab"), "repeat", &[Value::Number(count)]);
+        assert_eq!(repeat(3.0), Ok(Value::from("ababab")));
+        assert_eq!(repeat(0.0), Ok(Value::from("")));
+        assert!(repeat(-1.0).is_err());
+        assert!(repeat(1.5).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let list = numbers(&[1.0]);
+
+        assert_eq!(
+            call(&list, "remove", &[Value::Number(1.0)]),
+            Err(String::from("Index 1 is out of bounds for length 1"))
+        );
+        assert_eq!(
+            call(&list, "insert", &[Value::Number(-3.0), Value::Nil]),
+            Err(String::from("Index -3 is out of bounds for length 1"))
+        );
+        assert_eq!(
+            call(&list, "remove", &[Value::from("a")]),
+            Err(String::from(
+                "Invalid index: Expected number but found string"
+            ))
+        );
+        assert_eq!(
+            call(&list, "shuffle", &[]),
+            Err(String::from("Undefined method 'shuffle' for list"))
+        );
+        assert_eq!(
+            call(&Value::Nil, "len", &[]),
+            Err(String::from("Undefined method 'len' for nil"))
+        );
+
+        call(&list, "pop", &[]).unwrap();
+        assert!(call(&list, "pop", &[]).is_err());
+    }
+}
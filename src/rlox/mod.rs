@@ -1,12 +1,32 @@
 mod api;
+mod assembler;
+mod ast;
+mod bytecode;
 mod chunk;
+mod codegen;
 mod compiler;
+mod convert;
+mod debugger;
+mod diagnostic;
 mod disassembler;
+mod formatter;
 mod instruction;
+mod json;
+mod linter;
+mod lsp;
+mod map;
+mod methods;
+mod module;
+mod optimizer;
+mod parser;
+mod profiler;
 mod repl;
+mod script;
+mod stdlib;
 mod value;
 mod vm;
 mod scanner;
 mod token;
+mod trace;
 
 pub use api::*;
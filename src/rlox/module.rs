@@ -0,0 +1,319 @@
+use crate::rlox::ast::Import;
+use crate::rlox::chunk::Chunk;
+use crate::rlox::codegen::generate;
+use crate::rlox::diagnostic::{Diagnostic, Severity};
+use crate::rlox::parser::parse;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub enum ModuleError {
+    /// A module couldn't be read.
+    Io { path: PathBuf, error: io::Error },
+    /// A module doesn't compile, or one of its imports can't be resolved or
+    /// would close a cycle.
+    Compile {
+        path: PathBuf,
+        diagnostic: Diagnostic,
+    },
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleError::Io { path, error } => {
+                write!(f, "Could not read file \"{}\": {}", path.display(), error)
+            }
+            ModuleError::Compile { path, diagnostic } => {
+                write!(f, "{}: {}", path.display(), diagnostic)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
+
+struct Module {
+    chunk: Rc<Chunk>,
+    /// Each import with the path it resolved to.
+    imports: Vec<(Import, PathBuf)>,
+}
+
+/// Loads programs split across files. Imports are resolved relative to the
+/// file that has them first, then to each directory of the search path.
+///
+/// Modules are compiled once and cached by their canonical path, so a
+/// module imported from several places, or by several programs loaded with
+/// the same loader, is only compiled the first time.
+pub struct ModuleLoader {
+    search_path: Vec<PathBuf>,
+    modules: HashMap<PathBuf, Module>,
+}
+
+impl ModuleLoader {
+    /// A loader searching the directories listed in `LOX_PATH`, which are
+    /// separated like in `PATH`.
+    pub fn new() -> ModuleLoader {
+        let search_path = match env::var_os("LOX_PATH") {
+            Some(paths) => env::split_paths(&paths).collect(),
+            None => vec![],
+        };
+
+        ModuleLoader::with_search_path(search_path)
+    }
+
+    pub fn with_search_path(search_path: Vec<PathBuf>) -> ModuleLoader {
+        ModuleLoader {
+            search_path,
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Loads the program in `path` along with everything it imports, and
+    /// returns the chunks in the order they have to run: each module after
+    /// the ones it imports, and only once.
+    pub fn load(&mut self, path: &Path) -> Result<Vec<Rc<Chunk>>, ModuleError> {
+        let path = fs::canonicalize(path).map_err(|error| ModuleError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        let mut order = vec![];
+        self.visit(&path, &mut vec![], &mut HashSet::new(), &mut order)?;
+        Ok(order)
+    }
+
+    fn visit(
+        &mut self,
+        path: &Path,
+        importers: &mut Vec<PathBuf>,
+        visited: &mut HashSet<PathBuf>,
+        order: &mut Vec<Rc<Chunk>>,
+    ) -> Result<(), ModuleError> {
+        if visited.contains(path) {
+            return Ok(());
+        }
+
+        self.compile(path)?;
+        importers.push(path.to_path_buf());
+
+        let imports = self.modules[path].imports.clone();
+
+        for (import, resolved) in imports {
+            if let Some(start) = importers.iter().position(|importer| *importer == resolved) {
+                let cycle: Vec<String> = importers[start..]
+                    .iter()
+                    .chain(std::iter::once(&resolved))
+                    .map(|path| path.display().to_string())
+                    .collect();
+
+                return Err(error_at(
+                    path,
+                    &import,
+                    format!("Import cycle: {}", cycle.join(" -> ")),
+                ));
+            }
+
+            self.visit(&resolved, importers, visited, order)?;
+        }
+
+        importers.pop();
+        visited.insert(path.to_path_buf());
+        order.push(Rc::clone(&self.modules[path].chunk));
+        Ok(())
+    }
+
+    /// Compiles the module in `path`, unless it's already in the cache.
+    fn compile(&mut self, path: &Path) -> Result<(), ModuleError> {
+        if self.modules.contains_key(path) {
+            return Ok(());
+        }
+
+        let code = fs::read_to_string(path).map_err(|error| ModuleError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        let program = parse(&code).map_err(|diagnostic| ModuleError::Compile {
+            path: path.to_path_buf(),
+            diagnostic,
+        })?;
+
+        let mut imports = vec![];
+        for import in program.imports {
+            let resolved = self.resolve(path, &import.path).ok_or_else(|| {
+                error_at(
+                    path,
+                    &import,
+                    format!("Could not find module \"{}\"", import.path),
+                )
+            })?;
+
+            imports.push((import, resolved));
+        }
+
+        let mut chunk = Chunk::new();
+        generate(&program.expression, &mut chunk);
+
+        self.modules.insert(
+            path.to_path_buf(),
+            Module {
+                chunk: Rc::new(chunk),
+                imports,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn resolve(&self, importer: &Path, import: &str) -> Option<PathBuf> {
+        let directory = importer.parent().unwrap_or_else(|| Path::new(""));
+
+        std::iter::once(directory)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|directory| directory.join(import))
+            .find(|candidate| candidate.is_file())
+            .and_then(|candidate| fs::canonicalize(candidate).ok())
+    }
+}
+
+impl Default for ModuleLoader {
+    fn default() -> Self {
+        ModuleLoader::new()
+    }
+}
+
+fn error_at(path: &Path, import: &Import, message: String) -> ModuleError {
+    ModuleError::Compile {
+        path: path.to_path_buf(),
+        diagnostic: Diagnostic {
+            severity: Severity::Error,
+            line: import.line,
+            column: import.column,
+            token: Some(String::from("import")),
+            message,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::value::Value;
+    use crate::rlox::vm::Vm;
+
+    /// Creates an empty directory for a test, with the given files in it.
+    fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("rlox-modules-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        for (path, code) in files {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, code).unwrap();
+        }
+
+        directory
+    }
+
+    fn run(chunks: &[Rc<Chunk>]) -> Vec<Value> {
+        let mut vm = Vm::new();
+        chunks
+            .iter()
+            .map(|chunk| vm.interpret(chunk).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_modules_run_once_in_dependency_order() {
+        let directory = directory(
+            "order",
+            &[
+                ("main.lox", "import \"a.lox\";\nimport \"b.lox\";\n4"),
+                ("a.lox", "import \"lib/d.lox\";\n1"),
+                ("b.lox", "import \"lib/d.lox\";\n2"),
+                ("lib/d.lox", "3"),
+            ],
+        );
+
+        let mut loader = ModuleLoader::with_search_path(vec![]);
+        let chunks = loader.load(&directory.join("main.lox")).unwrap();
+
+        assert_eq!(
+            run(&chunks),
+            [3.0, 1.0, 2.0, 4.0].map(Value::Number).to_vec()
+        );
+
+        // Loading again reuses the cached chunks.
+        let again = loader.load(&directory.join("a.lox")).unwrap();
+        assert!(Rc::ptr_eq(&again[1], &chunks[1]));
+    }
+
+    #[test]
+    fn test_search_path() {
+        let directory = directory(
+            "search",
+            &[
+                ("app/main.lox", "import \"helpers.lox\";\n1"),
+                ("app/sub/local.lox", "2"),
+                ("shared/helpers.lox", "import \"local.lox\";\n3"),
+                ("shared/local.lox", "4"),
+            ],
+        );
+
+        let mut loader = ModuleLoader::with_search_path(vec![
+            directory.join("app/sub"),
+            directory.join("shared"),
+        ]);
+        let chunks = loader.load(&directory.join("app/main.lox")).unwrap();
+
+        // helpers.lox finds local.lox next to it before the search path.
+        assert_eq!(run(&chunks), [4.0, 3.0, 1.0].map(Value::Number).to_vec());
+    }
+
+    #[test]
+    fn test_errors() {
+        let directory = directory(
+            "errors",
+            &[
+                ("a.lox", "import \"b.lox\";\n1"),
+                ("b.lox", "// b\nimport \"a.lox\";\n2"),
+                ("missing.lox", "1 +\n  2 import \"x.lox\";"),
+                ("unknown.lox", "import \"nowhere.lox\";\n1"),
+            ],
+        );
+
+        let mut loader = ModuleLoader::with_search_path(vec![]);
+
+        let a = fs::canonicalize(directory.join("a.lox")).unwrap();
+        let b = fs::canonicalize(directory.join("b.lox")).unwrap();
+        assert_eq!(
+            loader.load(&a).unwrap_err().to_string(),
+            format!(
+                "{b}: [line 2:1] Error at 'import': Import cycle: {a} -> {b} -> {a}",
+                a = a.display(),
+                b = b.display()
+            )
+        );
+
+        let error = loader.load(&directory.join("unknown.lox")).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("[line 1:1] Error at 'import': Could not find module \"nowhere.lox\""));
+
+        assert!(matches!(
+            loader.load(&directory.join("missing.lox")),
+            Err(ModuleError::Compile { .. })
+        ));
+        assert!(matches!(
+            loader.load(&directory.join("nothing.lox")),
+            Err(ModuleError::Io { .. })
+        ));
+    }
+}
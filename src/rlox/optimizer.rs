@@ -0,0 +1,173 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::instruction::Instruction;
+
+/// Runs a peephole pass over a chunk, returning an equivalent chunk that
+/// should run faster. The rewrites are:
+///
+/// - `OpNot OpNot OpNot` becomes a single `OpNot`.
+/// - `OpNot OpNot` after an instruction that always leaves a boolean on the
+///   stack is removed, since it wouldn't change that boolean.
+/// - `OpConstant` followed by an arithmetic instruction is fused into a
+///   single superinstruction that takes the constant as an operand.
+///
+/// Each instruction that remains keeps its line. Fused instructions take the
+/// line of the arithmetic instruction, which is where runtime errors would be
+/// reported.
+pub fn optimize(chunk: &Chunk) -> Chunk {
+    let mut output: Vec<(Instruction, usize)> = vec![];
+
+    for (index, instruction) in chunk.instructions().enumerate() {
+        output.push((instruction.clone(), *chunk.line_at(index)));
+
+        // A rewrite can expose another one at the new end of the output, so
+        // keep going until nothing else applies.
+        while rewrite_tail(&mut output) {}
+    }
+
+    let mut optimized = Chunk::new();
+
+    for constant in chunk.constants() {
+        optimized.add_constant(constant.clone());
+    }
+
+    for (instruction, line) in output {
+        optimized.add_instruction(instruction, line);
+    }
+
+    optimized
+}
+
+/// Applies the first rewrite that matches the end of the output, returning
+/// whether any did.
+fn rewrite_tail(output: &mut Vec<(Instruction, usize)>) -> bool {
+    use Instruction::*;
+
+    let length = output.len();
+    let tail: Vec<&Instruction> = output.iter().rev().take(3).map(|(i, _)| i).collect();
+
+    match tail.as_slice() {
+        [OpNot, OpNot, OpNot, ..] => {
+            output.truncate(length - 2);
+            true
+        }
+        [OpNot, OpNot, previous, ..] if produces_boolean(previous) => {
+            output.truncate(length - 2);
+            true
+        }
+        [operation, OpConstant(index), ..] => {
+            let fused = match operation {
+                OpAdd => OpAddConstant(*index),
+                OpSubtract => OpSubtractConstant(*index),
+                OpMultiply => OpMultiplyConstant(*index),
+                OpDivide => OpDivideConstant(*index),
+                _ => return false,
+            };
+
+            let (_, line) = output.pop().unwrap();
+            output.pop();
+            output.push((fused, line));
+            true
+        }
+        _ => false,
+    }
+}
+
+fn produces_boolean(instruction: &Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(
+        instruction,
+        OpTrue
+            | OpFalse
+            | OpNot
+            | OpEqual
+            | OpNotEqual
+            | OpGreater
+            | OpGreaterEqual
+            | OpLess
+            | OpLessEqual
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::assembler::assemble;
+    use crate::rlox::compiler::compile;
+    use crate::rlox::vm::Vm;
+
+    fn optimize_assembly(assembly: &str) -> Chunk {
+        optimize(&assemble(assembly).expect("Error assembling code"))
+    }
+
+    #[test]
+    fn test_removes_double_not_after_boolean() {
+        let chunk = optimize_assembly("OpTrue\nOpNil\nOpEqual\nOpNot\nOpNot\nOpReturn");
+        assert_eq!(chunk, assemble("OpTrue\nOpNil\nOpEqual\nOpReturn").unwrap());
+    }
+
+    #[test]
+    fn test_keeps_double_not_that_converts_to_boolean() {
+        let chunk = optimize_assembly("OpNil\nOpNot\nOpNot\nOpReturn");
+        assert_eq!(chunk, assemble("OpNil\nOpNot\nOpNot\nOpReturn").unwrap());
+    }
+
+    #[test]
+    fn test_collapses_triple_not() {
+        let chunk = optimize_assembly("OpNil\nOpNot\nOpNot\nOpNot\nOpReturn");
+        assert_eq!(chunk, assemble("OpNil\nOpNot\nOpReturn").unwrap());
+    }
+
+    #[test]
+    fn test_fuses_constant_and_arithmetic() {
+        let chunk = optimize_assembly(
+            "1 OpConstant 1
+             1 OpConstant 2
+             2 OpAdd
+             2 OpConstant 3
+             3 OpDivide
+             3 OpReturn",
+        );
+
+        assert_eq!(
+            chunk,
+            assemble(
+                "1 OpConstant 0 1
+                 2 OpAddConstant 1 2
+                 3 OpDivideConstant 2 3
+                 3 OpReturn"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_optimized_chunk_gives_the_same_result() {
+        for assembly in &[
+            "OpConstant 1\nOpConstant 2\nOpAdd\nOpConstant 4\nOpMultiply\nOpReturn",
+            "OpConstant \"a\"\nOpConstant \"b\"\nOpAdd\nOpReturn",
+            "OpConstant 8\nOpConstant 2\nOpSubtract\nOpConstant 3\nOpDivide\nOpReturn",
+            "OpConstant 1\nOpConstant 2\nOpLess\nOpNot\nOpNot\nOpReturn",
+        ] {
+            let chunk = assemble(assembly).unwrap();
+            let optimized = optimize(&chunk);
+            assert!(optimized.instructions_count() < chunk.instructions_count());
+
+            assert_eq!(Vm::new().interpret(&optimized), Vm::new().interpret(&chunk));
+        }
+    }
+
+    #[test]
+    fn test_fused_instruction_errors_at_the_same_line() {
+        let mut chunk = Chunk::new();
+        compile("1 +\n\"a\"", &mut chunk).expect("Error compiling code");
+
+        let optimized = optimize(&chunk);
+        assert_eq!(
+            optimized.instruction_at(1),
+            Some(&Instruction::OpAddConstant(1))
+        );
+        assert_eq!(optimized.line_at(1), chunk.line_at(2));
+        assert!(Vm::new().interpret(&optimized).is_err());
+    }
+}
@@ -0,0 +1,523 @@
+use crate::rlox::ast::{BinaryOperator, Expr, Import, Literal, Program, UnaryOperator};
+use crate::rlox::compiler::{Precedence, MAX_ARGUMENTS};
+use crate::rlox::diagnostic::Diagnostic;
+use crate::rlox::scanner::{Scanner, ScannerIterator};
+use crate::rlox::token::{Token, TokenType};
+
+/// How deeply expressions can nest, so that a hostile input can't recurse
+/// until the stack overflows, in here or in whatever walks the AST after.
+const MAX_DEPTH: usize = 128;
+
+/// Parses the code into an AST. It accepts the same grammar as `compile`,
+/// plus the `import` declarations at the top of a file, and anything after
+/// the expression is an error instead of being ignored.
+pub fn parse(code: &str) -> Result<Program, Diagnostic> {
+    let mut scanner = Scanner::new();
+    let mut parser = Parser {
+        scanner: scanner.scan(code),
+        current: Token::new("", 1, 1, TokenType::Eof),
+        depth: 0,
+    };
+
+    parser.advance()?;
+
+    let mut imports = vec![];
+    while parser.current.token_type == TokenType::Import {
+        imports.push(parser.import()?);
+    }
+
+    let expression = parser.expression()?;
+
+    if parser.current.token_type != TokenType::Eof {
+        return Err(Diagnostic::error_at(
+            &parser.current,
+            "Expect end of expression",
+        ));
+    }
+
+    Ok(Program {
+        imports,
+        expression,
+    })
+}
+
+struct Parser<'code> {
+    scanner: ScannerIterator<'code>,
+    current: Token<'code>,
+    depth: usize,
+}
+
+impl<'code> Parser<'code> {
+    /// Moves to the next token, returning the one that was current.
+    fn advance(&mut self) -> Result<Token<'code>, Diagnostic> {
+        let next = match self.scanner.next() {
+            Some(token) if token.token_type == TokenType::Error => {
+                return Err(Diagnostic::error_at(&token, token.error_message().unwrap()))
+            }
+            Some(token) => token,
+            None => Token::new("", self.current.line, self.current.column, TokenType::Eof),
+        };
+
+        Ok(std::mem::replace(&mut self.current, next))
+    }
+
+    fn consume(
+        &mut self,
+        token_type: TokenType,
+        message: &str,
+    ) -> Result<Token<'code>, Diagnostic> {
+        if self.current.token_type == token_type {
+            self.advance()
+        } else {
+            Err(Diagnostic::error_at(&self.current, message))
+        }
+    }
+
+    fn import(&mut self) -> Result<Import, Diagnostic> {
+        let keyword = self.advance()?;
+        let path = self.consume(TokenType::String, "Expect a path string after 'import'")?;
+        self.consume(TokenType::Semicolon, "Expect ';' after import")?;
+
+        Ok(Import {
+            path: String::from(&path.code[1..path.code.len() - 1]),
+            line: keyword.line,
+            column: keyword.column,
+        })
+    }
+
+    fn expression(&mut self) -> Result<Expr, Diagnostic> {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<Expr, Diagnostic> {
+        if self.depth == MAX_DEPTH {
+            let message = format!("Too deeply nested, the limit is {}", MAX_DEPTH);
+            return Err(Diagnostic::error_at(&self.current, &message));
+        }
+
+        self.depth += 1;
+        let expression = self.nested_expression(precedence);
+        self.depth -= 1;
+        expression
+    }
+
+    fn nested_expression(&mut self, precedence: Precedence) -> Result<Expr, Diagnostic> {
+        let can_assign = precedence <= Precedence::Assignment;
+        let token = self.advance()?;
+
+        let mut expression = match token.token_type {
+            TokenType::Minus | TokenType::Bang => Expr::Unary {
+                operator: UnaryOperator::from_token_type(token.token_type).unwrap(),
+                operand: Box::new(self.parse_precedence(Precedence::Unary)?),
+                line: token.line,
+                column: token.column,
+            },
+            TokenType::LeftParen => {
+                let expression = self.expression()?;
+                let closing = self.consume(TokenType::RightParen, "Expect ')' after expression")?;
+
+                Expr::Grouping {
+                    expression: Box::new(expression),
+                    line: token.line,
+                    column: token.column,
+                    end_line: closing.line,
+                }
+            }
+            TokenType::Number => literal(&token, Literal::Number(token.code.parse().unwrap())),
+            TokenType::String => literal(
+                &token,
+                Literal::String(String::from(&token.code[1..token.code.len() - 1])),
+            ),
+            TokenType::True => literal(&token, Literal::True),
+            TokenType::False => literal(&token, Literal::False),
+            TokenType::Nil => literal(&token, Literal::Nil),
+            TokenType::LeftBracket => {
+                let elements = self.expression_list(TokenType::RightBracket)?;
+                let closing =
+                    self.consume(TokenType::RightBracket, "Expect ']' after list elements")?;
+
+                Expr::List {
+                    elements,
+                    line: token.line,
+                    column: token.column,
+                    end_line: closing.line,
+                }
+            }
+            TokenType::LeftBrace => {
+                let entries = self.map_entries()?;
+                let closing =
+                    self.consume(TokenType::RightBrace, "Expect '}' after map entries")?;
+
+                Expr::Map {
+                    entries,
+                    line: token.line,
+                    column: token.column,
+                    end_line: closing.line,
+                }
+            }
+            TokenType::Identifier => Expr::Variable {
+                name: String::from(token.code),
+                line: token.line,
+                column: token.column,
+            },
+            _ => return Err(Diagnostic::error_at(&token, "Expected prefix expression")),
+        };
+
+        while precedence <= self.current.token_type.precedence() {
+            let token = self.advance()?;
+
+            match token.token_type {
+                TokenType::LeftParen => {
+                    let arguments = self.expression_list(TokenType::RightParen)?;
+                    let closing =
+                        self.consume(TokenType::RightParen, "Expect ')' after arguments")?;
+
+                    if arguments.len() > MAX_ARGUMENTS {
+                        return Err(Diagnostic::error_at(
+                            &closing,
+                            "Can't have more than 255 arguments",
+                        ));
+                    }
+
+                    expression = Expr::Call {
+                        callee: Box::new(expression),
+                        arguments,
+                        line: token.line,
+                        column: token.column,
+                        end_line: closing.line,
+                    };
+                    continue;
+                }
+                TokenType::LeftBracket if self.current.token_type == TokenType::Colon => {
+                    expression = self.slice(expression, None, &token)?;
+                    continue;
+                }
+                TokenType::LeftBracket => {
+                    let index = self.expression()?;
+
+                    if self.current.token_type == TokenType::Colon {
+                        expression = self.slice(expression, Some(index), &token)?;
+                        continue;
+                    }
+
+                    let closing =
+                        self.consume(TokenType::RightBracket, "Expect ']' after index")?;
+
+                    expression = if can_assign && self.current.token_type == TokenType::Equal {
+                        self.advance()?;
+
+                        Expr::SetIndex {
+                            object: Box::new(expression),
+                            index: Box::new(index),
+                            value: Box::new(self.expression()?),
+                            line: token.line,
+                            column: token.column,
+                        }
+                    } else {
+                        Expr::Index {
+                            object: Box::new(expression),
+                            index: Box::new(index),
+                            line: token.line,
+                            column: token.column,
+                            end_line: closing.line,
+                        }
+                    };
+                    continue;
+                }
+                TokenType::Dot => {
+                    let name =
+                        self.consume(TokenType::Identifier, "Expect property name after '.'")?;
+
+                    expression = Expr::Get {
+                        object: Box::new(expression),
+                        name: String::from(name.code),
+                        line: name.line,
+                        column: name.column,
+                    };
+                    continue;
+                }
+                _ => {}
+            }
+
+            let operator = BinaryOperator::from_token_type(token.token_type)
+                .ok_or_else(|| Diagnostic::error_at(&token, "Expected infix expression"))?;
+            let right = self.parse_precedence(token.token_type.precedence().higher())?;
+
+            expression = Expr::Binary {
+                left: Box::new(expression),
+                operator,
+                right: Box::new(right),
+                line: token.line,
+                column: token.column,
+            };
+        }
+
+        if can_assign && self.current.token_type == TokenType::Equal {
+            return Err(Diagnostic::error_at(
+                &self.current,
+                "Invalid assignment target",
+            ));
+        }
+
+        Ok(expression)
+    }
+
+    /// Parses the rest of a slice from its colon. A missing bound is a `nil`
+    /// literal at the colon, which is what gets compiled for it.
+    fn slice(
+        &mut self,
+        object: Expr,
+        start: Option<Expr>,
+        bracket: &Token,
+    ) -> Result<Expr, Diagnostic> {
+        let colon = self.advance()?;
+        let start = start.unwrap_or_else(|| literal(&colon, Literal::Nil));
+        let end = if self.current.token_type == TokenType::RightBracket {
+            literal(&colon, Literal::Nil)
+        } else {
+            self.expression()?
+        };
+        let closing = self.consume(TokenType::RightBracket, "Expect ']' after slice")?;
+
+        Ok(Expr::Slice {
+            object: Box::new(object),
+            start: Box::new(start),
+            end: Box::new(end),
+            line: bracket.line,
+            column: bracket.column,
+            end_line: closing.line,
+        })
+    }
+
+    /// Parses the comma-separated `key: value` entries of a map up to the
+    /// closing brace, which is left for the caller to consume. A `{` can only
+    /// start a map where an expression is expected, since blocks are never
+    /// part of an expression.
+    fn map_entries(&mut self) -> Result<Vec<(Expr, Expr)>, Diagnostic> {
+        let mut entries = vec![];
+
+        if self.current.token_type != TokenType::RightBrace {
+            loop {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after map key")?;
+                entries.push((key, self.expression()?));
+
+                if self.current.token_type != TokenType::Comma {
+                    break;
+                }
+                self.advance()?;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Parses comma-separated expressions up to a closing token, which is
+    /// left for the caller to consume.
+    fn expression_list(&mut self, closing: TokenType) -> Result<Vec<Expr>, Diagnostic> {
+        let mut expressions = vec![];
+
+        if self.current.token_type != closing {
+            loop {
+                expressions.push(self.expression()?);
+
+                if self.current.token_type != TokenType::Comma {
+                    break;
+                }
+                self.advance()?;
+            }
+        }
+
+        Ok(expressions)
+    }
+}
+
+fn literal(token: &Token, value: Literal) -> Expr {
+    Expr::Literal {
+        value,
+        line: token.line,
+        column: token.column,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_to_string(code: &str) -> String {
+        parse(code).expect("Error parsing code").to_string()
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        assert_eq!(parse_to_string("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(parse_to_string("1 - 2 - 3"), "(- (- 1 2) 3)");
+        assert_eq!(
+            parse_to_string("-(1 + 2) >= 3 == !true"),
+            "(== (>= (- (group (+ 1 2))) 3) (! true))"
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(parse_to_string("1.5"), "1.5");
+        assert_eq!(parse_to_string("\"a \\ b\""), "\"a \\\\ b\"");
+        assert_eq!(parse_to_string("nil != false"), "(!= nil false)");
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(parse_to_string("clock()"), "(call clock)");
+        assert_eq!(
+            parse_to_string("-max(1, f(2)(3)) * 2"),
+            "(* (- (call max 1 (call (call f 2) 3))) 2)"
+        );
+        assert_eq!(
+            parse(
+                "f(1,
+ 2"
+            )
+            .unwrap_err()
+            .to_string(),
+            "[line 2:3] Error: Expect ')' after arguments"
+        );
+        assert_eq!(
+            parse("f(1,)").unwrap_err().to_string(),
+            "[line 1:5] Error at ')': Expected prefix expression"
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(
+            parse_to_string("[1, [], -x[0]][1] = [2].len()"),
+            "(set-index (list 1 (list) (- (index x 0))) 1 (call (get (list 2) len)))"
+        );
+        assert_eq!(
+            parse("1 + x[0] = 2").unwrap_err().to_string(),
+            "[line 1:10] Error at '=': Invalid assignment target"
+        );
+        assert_eq!(
+            parse("[1, 2").unwrap_err().to_string(),
+            "[line 1:6] Error: Expect ']' after list elements"
+        );
+        assert_eq!(
+            parse("x.1").unwrap_err().to_string(),
+            "[line 1:3] Error at '1': Expect property name after '.'"
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        assert_eq!(
+            parse_to_string("{\"a\": [1], 2: {}}[x] = {nil: -1}"),
+            "(set-index (map \"a\" (list 1) 2 (map)) x (map nil (- 1)))"
+        );
+        assert_eq!(
+            parse("{1, 2}").unwrap_err().to_string(),
+            "[line 1:3] Error at ',': Expect ':' after map key"
+        );
+        assert_eq!(
+            parse("{1: 2").unwrap_err().to_string(),
+            "[line 1:6] Error: Expect '}' after map entries"
+        );
+    }
+
+    #[test]
+    fn test_slices() {
+        assert_eq!(
+            parse_to_string("s[1:-1] + s[:2][1:][:]"),
+            "(+ (slice s 1 (- 1)) (slice (slice (slice s nil 2) 1 nil) nil nil))"
+        );
+        assert_eq!(
+            parse("s[1:2] = 3").unwrap_err().to_string(),
+            "[line 1:8] Error at '=': Invalid assignment target"
+        );
+        assert_eq!(
+            parse("s[1:2:3]").unwrap_err().to_string(),
+            "[line 1:6] Error at ':': Expect ']' after slice"
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let expression = parse("1 +\n(2\n)").unwrap().expression;
+
+        match &expression {
+            Expr::Binary { line, .. } => assert_eq!(*line, 1),
+            _ => panic!("Expected a binary expression"),
+        }
+        assert_eq!(expression.end_line(), 3);
+    }
+
+    #[test]
+    fn test_imports() {
+        let program = parse("import \"lib.lox\";\n import \"a/b.lox\"; 1").unwrap();
+
+        assert_eq!(
+            program.imports,
+            [
+                Import {
+                    path: String::from("lib.lox"),
+                    line: 1,
+                    column: 1
+                },
+                Import {
+                    path: String::from("a/b.lox"),
+                    line: 2,
+                    column: 2
+                }
+            ]
+        );
+        assert_eq!(
+            program.to_string(),
+            "(import \"lib.lox\")\n(import \"a/b.lox\")\n1"
+        );
+
+        assert_eq!(
+            parse("import lib; 1").unwrap_err().to_string(),
+            "[line 1:8] Error at 'lib': Expect a path string after 'import'"
+        );
+        assert_eq!(
+            parse("1 + 2 import \"a\";").unwrap_err().to_string(),
+            "[line 1:7] Error at 'import': Expect end of expression"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse("(1 + 2").unwrap_err().to_string(),
+            "[line 1:7] Error: Expect ')' after expression"
+        );
+        assert_eq!(
+            parse("1 +\n )").unwrap_err().to_string(),
+            "[line 2:2] Error at ')': Expected prefix expression"
+        );
+        assert_eq!(
+            parse("1 2").unwrap_err().to_string(),
+            "[line 1:3] Error at '2': Expect end of expression"
+        );
+        assert_eq!(
+            parse("\"open").unwrap_err().to_string(),
+            "[line 1:1] Error: Unterminated string."
+        );
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let parens = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        let message = format!("Too deeply nested, the limit is {}", MAX_DEPTH);
+
+        assert!(parse(&parens(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(parse(&parens(MAX_DEPTH)).unwrap_err().message, message);
+        assert_eq!(parse(&parens(50_000)).unwrap_err().message, message);
+        assert_eq!(
+            parse(&format!("{}1", "-".repeat(50_000)))
+                .unwrap_err()
+                .message,
+            message
+        );
+        assert!(parse(&"[".repeat(50_000)).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::trace::VmHook;
+use crate::rlox::value::Value;
+use crate::rlox::vm::Vm;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Counts the instructions executed by the VM, by opcode and by source line,
+/// and measures the time spent in each function.
+#[derive(Default)]
+pub struct Profiler {
+    by_opcode: HashMap<&'static str, u64>,
+    by_line: HashMap<usize, u64>,
+    by_function: HashMap<String, FunctionTime>,
+    calls: Vec<(String, Instant)>,
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct FunctionTime {
+    calls: u64,
+    total: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn report(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "== Instructions by opcode ==")?;
+        for (opcode, count) in sorted_by_count(&self.by_opcode) {
+            writeln!(out, "{: >10} {}", count, opcode)?;
+        }
+
+        writeln!(out, "== Instructions by line ==")?;
+        for (line, count) in sorted_by_count(&self.by_line) {
+            writeln!(out, "{: >10} line {}", count, line)?;
+        }
+
+        writeln!(out, "== Time by function ==")?;
+        let mut functions: Vec<_> = self.by_function.iter().collect();
+        functions.sort_by(|(a_name, a), (b_name, b)| {
+            b.total.cmp(&a.total).then_with(|| a_name.cmp(b_name))
+        });
+        for (name, time) in functions {
+            writeln!(out, "{: >10?} {} ({} calls)", time.total, name, time.calls)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish_call(&mut self) {
+        if let Some((name, start)) = self.calls.pop() {
+            let time = self.by_function.entry(name).or_default();
+            time.calls += 1;
+            time.total += start.elapsed();
+        }
+    }
+}
+
+/// Sorts the entries by descending count, breaking ties by key so that the
+/// report is stable.
+fn sorted_by_count<K: Ord + Copy>(counts: &HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut entries: Vec<(K, u64)> = counts.iter().map(|(k, v)| (*k, *v)).collect();
+    entries.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+    entries
+}
+
+impl VmHook for Profiler {
+    fn on_call(&mut self, name: &str) {
+        self.calls.push((String::from(name), Instant::now()));
+    }
+
+    fn on_instruction(&mut self, _vm: &Vm, chunk: &Chunk, index: usize) {
+        if let Some(instruction) = chunk.instruction_at(index) {
+            *self.by_opcode.entry(instruction.name()).or_insert(0) += 1;
+        }
+
+        *self.by_line.entry(*chunk.line_at(index)).or_insert(0) += 1;
+    }
+
+    fn on_return(&mut self, _value: &Value) {
+        self.finish_call();
+    }
+
+    fn on_runtime_error(&mut self, _message: &str, _line: usize) {
+        // A runtime error unwinds every call in progress.
+        while !self.calls.is_empty() {
+            self.finish_call();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::instruction::Instruction;
+    use crate::rlox::vm::Vm;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_counts() {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut vm = Vm::new();
+        vm.set_hook(Box::new(Rc::clone(&profiler)));
+
+        let mut chunk = Chunk::new();
+        let constant_index = chunk.add_constant(Value::Number(1.0));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 2);
+        chunk.add_instruction(Instruction::OpAdd, 2);
+        chunk.add_instruction(Instruction::OpReturn, 2);
+        vm.interpret(&chunk).expect("Error running chunk");
+
+        let profiler = profiler.borrow();
+        assert_eq!(
+            sorted_by_count(&profiler.by_opcode),
+            vec![("OpConstant", 2), ("OpAdd", 1), ("OpReturn", 1)]
+        );
+        assert_eq!(sorted_by_count(&profiler.by_line), vec![(2, 3), (1, 1)]);
+        assert_eq!(profiler.by_function["script"].calls, 1);
+        assert!(profiler.calls.is_empty());
+    }
+}
@@ -1,32 +1,296 @@
 use crate::rlox::chunk::Chunk;
-use crate::rlox::compiler::compile;
+use crate::rlox::compiler::{compile, compile_with_diagnostics};
+use crate::rlox::disassembler::disassemble;
+use crate::rlox::scanner::Scanner;
+use crate::rlox::token::TokenType;
+use crate::rlox::value::Value;
 use crate::rlox::vm::InterpretError;
 use crate::rlox::vm::Vm;
+use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::time::Instant;
 
 pub fn repl() -> Result<(), InterpretError> {
     println!("Welcome to the rlox prompt");
-    println!("^D to exit\n");
+    println!("^D to exit, :help for a list of commands\n");
+
+    // A single VM lives for the whole session, so that state created by one
+    // line is still around for the next one.
+    let mut vm = Vm::new();
+
+    while let Some(input) = read_input() {
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        if input.trim_start().starts_with(':') {
+            match Command::parse(&input) {
+                Ok(command) => run_command(&mut vm, command)?,
+                Err(message) => eprintln!("{}", message),
+            }
+
+            continue;
+        }
+
+        if let Some(value) = unless_exit(interpret(&mut vm, &input, &mut io::stderr()))? {
+            println!("{}", value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Meta-commands available in the REPL, introduced by a colon.
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Help,
+    Disassemble(&'a str),
+    Stack,
+    Globals,
+    Load(&'a str),
+    Reset,
+    Time(&'a str),
+}
+
+impl<'a> Command<'a> {
+    fn parse(input: &'a str) -> Result<Command<'a>, String> {
+        let input = input.trim().trim_start_matches(':');
+        let (name, argument) = match input.find(char::is_whitespace) {
+            Some(index) => (&input[..index], input[index..].trim()),
+            None => (input, ""),
+        };
+
+        let command = match name {
+            "help" => Command::Help,
+            "dis" => Command::Disassemble(argument),
+            "stack" => Command::Stack,
+            "globals" => Command::Globals,
+            "load" => Command::Load(argument),
+            "reset" => Command::Reset,
+            "time" => Command::Time(argument),
+            _ => return Err(format!("Unknown command ':{}', try :help", name)),
+        };
+
+        match command {
+            Command::Disassemble("") | Command::Time("") => Err(format!("Usage: :{} <expr>", name)),
+            Command::Load("") => Err(String::from("Usage: :load <file>")),
+            _ => Ok(command),
+        }
+    }
+}
+
+fn run_command(vm: &mut Vm, command: Command) -> Result<(), InterpretError> {
+    match command {
+        Command::Help => {
+            println!(":dis <expr>    Show the bytecode for an expression");
+            println!(":stack         Show the contents of the VM stack");
+            println!(":globals       List the globals defined in this session");
+            println!(":load <file>   Run a file into this session");
+            println!(":reset         Drop all the state of this session");
+            println!(":time <expr>   Run an expression and show how long it took");
+        }
+        Command::Disassemble(code) => {
+            let mut chunk = Chunk::new();
+
+            if compile(code, &mut chunk).is_ok() {
+                disassemble(&chunk, code, &mut io::stdout()).expect("Error writing disassembly");
+            }
+        }
+        Command::Stack => {
+            for value in vm.stack() {
+                print!("[ {} ]", value);
+            }
+
+            println!();
+        }
+        Command::Globals => {
+            let mut globals: Vec<_> = vm.globals().collect();
+            globals.sort_by_key(|(name, _)| *name);
+
+            for (name, value) in globals {
+                println!("{} = {}", name, value);
+            }
+        }
+        Command::Load(path) => match fs::read_to_string(path) {
+            Ok(code) => {
+                unless_exit(interpret(vm, &code, &mut io::stderr()))?;
+            }
+            Err(error) => eprintln!("Could not read '{}': {}", path, error),
+        },
+        Command::Reset => *vm = Vm::new(),
+        Command::Time(code) => {
+            let start = Instant::now();
+            let result = interpret(vm, code, &mut io::stderr());
+            let elapsed = start.elapsed();
+
+            if let Some(value) = unless_exit(result)? {
+                println!("{}", value);
+            }
+
+            println!("Took {:?}", elapsed);
+        }
+    }
+
+    Ok(())
+}
+
+/// Errors have already been reported by the time they get here, so the
+/// session just moves on to the next input. Only `exit` ends it.
+fn unless_exit(result: Result<Value, InterpretError>) -> Result<Option<Value>, InterpretError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(InterpretError::Exit(code)) => Err(InterpretError::Exit(code)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads lines from stdin until they form a complete piece of code, prompting
+/// with `...` for continuation lines. Returns `None` once stdin is closed.
+fn read_input() -> Option<String> {
+    let mut input = String::new();
+    let mut prompt = "> ";
 
     loop {
-        print!("> ");
+        print!("{}", prompt);
         io::stdout().flush().expect("Error flushing stdout");
 
-        let mut input = String::new();
-        io::stdin()
+        let bytes_read = io::stdin()
             .read_line(&mut input)
             .expect("Error: unable to read user input");
 
-        interpret(&input)?;
+        if bytes_read == 0 {
+            // ^D on an empty prompt ends the session, ^D in the middle of a
+            // multi-line input still gets that input evaluated.
+            println!();
+            return if input.trim().is_empty() {
+                None
+            } else {
+                Some(input)
+            };
+        }
+
+        if !is_incomplete(&input) {
+            return Some(input);
+        }
+
+        prompt = "... ";
+    }
+}
+
+/// Whether the code so far is missing closing delimiters, which means the user
+/// is still in the middle of typing it.
+fn is_incomplete(code: &str) -> bool {
+    let mut scanner = Scanner::new();
+    let mut depth = 0;
+
+    for token in scanner.scan(code) {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+            TokenType::Error if token.error_message() == Some("Unterminated string.") => {
+                return true
+            }
+            _ => {}
+        }
     }
+
+    depth > 0
 }
 
-fn interpret(code: &String) -> Result<(), InterpretError> {
+/// Compiles and runs a piece of code, writing compile errors to `errors`.
+/// Runtime errors are reported by the VM itself.
+fn interpret(vm: &mut Vm, code: &str, errors: &mut dyn Write) -> Result<Value, InterpretError> {
     let mut chunk = Chunk::new();
-    compile(code, &mut chunk)?;
 
-    // TODO: Persist the VM across the REPL session
-    let mut vm = Vm::new();
+    if let Err(diagnostics) = compile_with_diagnostics(code, &mut chunk) {
+        for diagnostic in diagnostics {
+            let _ = writeln!(errors, "{}", diagnostic);
+        }
+
+        return Err(InterpretError::CompileError);
+    }
+
     vm.interpret(&chunk)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_input() {
+        assert!(!is_incomplete("1 + 2\n"));
+        assert!(!is_incomplete("(1 + 2) * 3\n"));
+        assert!(!is_incomplete("\"a string\"\n"));
+    }
+
+    #[test]
+    fn test_unbalanced_delimiters_are_incomplete() {
+        assert!(is_incomplete("(1 + 2\n"));
+        assert!(is_incomplete("((1 + 2) * 3\n"));
+        assert!(is_incomplete("{\n"));
+        assert!(is_incomplete("[1,\n"));
+    }
+
+    #[test]
+    fn test_unterminated_string_is_incomplete() {
+        assert!(is_incomplete("\"unterminated\n"));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::parse(":stack\n"), Ok(Command::Stack));
+        assert_eq!(
+            Command::parse(":dis 1 + 2\n"),
+            Ok(Command::Disassemble("1 + 2"))
+        );
+        assert_eq!(
+            Command::parse(":load  script.lox \n"),
+            Ok(Command::Load("script.lox"))
+        );
+    }
+
+    #[test]
+    fn test_parse_command_errors() {
+        assert!(Command::parse(":nope\n").is_err());
+        assert!(Command::parse(":time\n").is_err());
+        assert!(Command::parse(":load\n").is_err());
+    }
+
+    #[test]
+    fn test_extra_closing_delimiter_is_not_incomplete() {
+        assert!(!is_incomplete("1 + 2)\n"));
+    }
+
+    // The language can't define globals yet, so the one here comes from the
+    // embedding API. This only checks that a runtime error clears the stack
+    // without dropping the globals the session's VM already had.
+    #[test]
+    fn test_runtime_error_keeps_existing_globals() {
+        let mut vm = Vm::new();
+        vm.define_global("answer", Value::Number(42.0));
+
+        assert_eq!(
+            interpret(&mut vm, "answer + nil\n", &mut io::sink()),
+            Err(InterpretError::RuntimeError)
+        );
+        assert!(vm.stack().is_empty());
+        assert_eq!(
+            interpret(&mut vm, "answer\n", &mut io::sink()),
+            Ok(Value::Number(42.0))
+        );
+    }
+
+    #[test]
+    fn test_incomplete_input_is_reported() {
+        let mut errors = vec![];
+        let result = interpret(&mut Vm::new(), "1 +\n", &mut errors);
+
+        assert_eq!(result, Err(InterpretError::CompileError));
+        assert_eq!(
+            String::from_utf8(errors).unwrap(),
+            "[line 2:1] Error: Expect expression\n"
+        );
+    }
+}
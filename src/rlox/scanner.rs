@@ -1,5 +1,6 @@
 use crate::rlox::token::{Token, TokenType};
 
+#[derive(Default)]
 pub struct Scanner {}
 
 impl Scanner {
@@ -13,7 +14,31 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            column: 1,
             is_over: false,
+            keep_comments: false,
+            keep_whitespace: false,
+        }
+    }
+
+    /// Like `scan`, but comments come out as `Comment` tokens instead of
+    /// being skipped, for tools that need to preserve them.
+    pub fn scan_with_comments<'a>(&mut self, code: &'a str) -> ScannerIterator<'a> {
+        ScannerIterator {
+            keep_comments: true,
+            ..self.scan(code)
+        }
+    }
+
+    /// Like `scan`, but nothing in the code is left out: whitespace and
+    /// comments come out as `Whitespace` and `Comment` tokens, so the code of
+    /// all the tokens put together is the original code.
+    pub fn scan_with_trivia<'a>(&mut self, code: &'a str) -> ScannerIterator<'a> {
+        ScannerIterator {
+            keep_comments: true,
+            keep_whitespace: true,
+            ..self.scan(code)
         }
     }
 }
@@ -23,34 +48,34 @@ pub struct ScannerIterator<'code> {
     start: usize,
     current: usize,
     line: usize,
+    /// Where the current line starts in the code.
+    line_start: usize,
+    /// The column the token being scanned starts at.
+    column: usize,
     is_over: bool,
+    keep_comments: bool,
+    keep_whitespace: bool,
 }
 
 impl<'code> ScannerIterator<'code> {
     fn advance(&mut self) -> Option<&'code str> {
-        if !self.is_at_end() {
-            let current = self.current;
-            self.current += 1;
-            Some(&self.code[current..current + 1])
-        } else {
-            None
-        }
+        let c = self.peek()?;
+        self.current += c.len();
+        Some(c)
     }
 
+    // Characters are returned as slices of the code, which can be several
+    // bytes long for anything outside of ASCII.
     fn peek(&self) -> Option<&'code str> {
-        if !self.is_at_end() {
-            Some(&self.code[self.current..self.current + 1])
-        } else {
-            None
-        }
+        let c = self.code[self.current..].chars().next()?;
+        Some(&self.code[self.current..self.current + c.len_utf8()])
     }
 
     fn peek_next(&self) -> Option<&'code str> {
-        if self.current + 1 < self.code.len() {
-            Some(&self.code[self.current + 1..self.current + 2])
-        } else {
-            None
-        }
+        let mut chars = self.code[self.current..].char_indices().skip(1);
+        let (offset, c) = chars.next()?;
+        let start = self.current + offset;
+        Some(&self.code[start..start + c.len_utf8()])
     }
 
     fn match_char(&mut self, expected: &str) -> bool {
@@ -58,7 +83,7 @@ impl<'code> ScannerIterator<'code> {
             return false;
         }
 
-        if self.peek().map_or(true, |c| c != expected) {
+        if self.peek() != Some(expected) {
             return false;
         }
 
@@ -71,20 +96,16 @@ impl<'code> ScannerIterator<'code> {
     }
 
     fn build_token(&self, code: &'code str, token_type: TokenType) -> Token<'code> {
-        Token::new(code, self.line, token_type)
+        Token::new(code, self.line, self.column, token_type)
     }
 
     fn string(&mut self) -> Token<'code> {
         while self.peek() != Some("\"") && !self.is_at_end() {
-            if self.peek() == Some("\n") {
-                self.line += 1;
-            }
-
-            self.advance();
+            self.advance_line();
         }
 
         if self.is_at_end() {
-            return self.build_token("Unterminated string.", TokenType::Error);
+            return self.build_token(&self.code[self.start..self.current], TokenType::Error);
         }
 
         // Closing quote
@@ -93,14 +114,14 @@ impl<'code> ScannerIterator<'code> {
     }
 
     fn number(&mut self) -> Token<'code> {
-        while self.peek().map_or(false, |digit| is_digit(digit)) {
+        while self.peek().is_some_and(is_digit) {
             self.advance();
         }
 
-        if self.peek() == Some(".") && self.peek_next().map_or(false, |digit| is_digit(digit)) {
+        if self.peek() == Some(".") && self.peek_next().is_some_and(is_digit) {
             self.advance();
 
-            while self.peek().map_or(false, |digit| is_digit(digit)) {
+            while self.peek().is_some_and(is_digit) {
                 self.advance();
             }
         }
@@ -111,7 +132,7 @@ impl<'code> ScannerIterator<'code> {
     fn identifier_or_keyword(&mut self) -> Token<'code> {
         while self
             .peek()
-            .map_or(false, |alpha| is_alpha(alpha) || is_digit(alpha))
+            .is_some_and(|alpha| is_alpha(alpha) || is_digit(alpha))
         {
             self.advance();
         }
@@ -124,7 +145,6 @@ impl<'code> ScannerIterator<'code> {
             "a" => self.check_keyword(1, "nd", TokenType::And),
             "c" => self.check_keyword(1, "lass", TokenType::Class),
             "e" => self.check_keyword(1, "lse", TokenType::Else),
-            "i" => self.check_keyword(1, "f", TokenType::If),
             "n" => self.check_keyword(1, "il", TokenType::Nil),
             "o" => self.check_keyword(1, "r", TokenType::Or),
             "p" => self.check_keyword(1, "rint", TokenType::Print),
@@ -140,6 +160,13 @@ impl<'code> ScannerIterator<'code> {
                     _ => self.build_identifier_token(),
                 }
             }
+            "i" if self.current - self.start > 1 => {
+                match &self.code[self.start + 1..self.start + 2] {
+                    "f" => self.check_keyword(1, "f", TokenType::If),
+                    "m" => self.check_keyword(1, "mport", TokenType::Import),
+                    _ => self.build_identifier_token(),
+                }
+            }
             "t" if self.current - self.start > 1 => {
                 match &self.code[self.start + 1..self.start + 2] {
                     "h" => self.check_keyword(2, "is", TokenType::This),
@@ -165,17 +192,43 @@ impl<'code> ScannerIterator<'code> {
         }
     }
 
+    /// Advances past the next character, keeping track of where lines start
+    /// when it's a new line.
+    fn advance_line(&mut self) {
+        if self.advance() == Some("\n") {
+            self.line += 1;
+            self.line_start = self.current;
+        }
+    }
+
+    fn comment(&mut self) -> Token<'code> {
+        while !self.is_at_end() && self.peek() != Some("\n") {
+            self.advance();
+        }
+
+        self.build_token(&self.code[self.start..self.current], TokenType::Comment)
+    }
+
+    fn whitespace(&mut self) -> Token<'code> {
+        while matches!(self.peek(), Some(" " | "\r" | "\t" | "\n")) {
+            self.advance_line();
+        }
+
+        self.build_token(&self.code[self.start..self.current], TokenType::Whitespace)
+    }
+
     fn skip_whitespace(&mut self) {
+        if self.keep_whitespace {
+            return;
+        }
+
         loop {
             match self.peek() {
                 Some(" ") | Some("\r") | Some("\t") => {
                     self.advance();
                 }
-                Some("\n") => {
-                    self.line += 1;
-                    self.advance();
-                }
-                Some("/") => {
+                Some("\n") => self.advance_line(),
+                Some("/") if !self.keep_comments => {
                     if let Some("/") = self.peek_next() {
                         // Started a comment
                         let mut next = self.peek();
@@ -205,18 +258,29 @@ impl<'code> Iterator for ScannerIterator<'code> {
         self.skip_whitespace();
 
         self.start = self.current;
+        self.column = self.code[self.line_start..self.start].chars().count() + 1;
         let c = self.advance();
 
         match c {
+            Some(" " | "\r" | "\t" | "\n") => {
+                // Only reachable when whitespace is kept, it's skipped
+                // before getting here otherwise.
+                self.current = self.start;
+                Some(self.whitespace())
+            }
             Some("(") => Some(self.build_token("(", TokenType::LeftParen)),
             Some(")") => Some(self.build_token(")", TokenType::RightParen)),
             Some("{") => Some(self.build_token("{", TokenType::LeftBrace)),
             Some("}") => Some(self.build_token("}", TokenType::RightBrace)),
+            Some("[") => Some(self.build_token("[", TokenType::LeftBracket)),
+            Some("]") => Some(self.build_token("]", TokenType::RightBracket)),
             Some(";") => Some(self.build_token(";", TokenType::Semicolon)),
             Some(",") => Some(self.build_token(",", TokenType::Comma)),
+            Some(":") => Some(self.build_token(":", TokenType::Colon)),
             Some(".") => Some(self.build_token(".", TokenType::Dot)),
             Some("-") => Some(self.build_token("-", TokenType::Minus)),
             Some("+") => Some(self.build_token("+", TokenType::Plus)),
+            Some("/") if self.keep_comments && self.peek() == Some("/") => Some(self.comment()),
             Some("/") => Some(self.build_token("/", TokenType::Slash)),
             Some("*") => Some(self.build_token("*", TokenType::Star)),
             Some("!") => {
@@ -244,13 +308,15 @@ impl<'code> Iterator for ScannerIterator<'code> {
                 if self.match_char("=") {
                     Some(self.build_token(">=", TokenType::GreaterEqual))
                 } else {
-                    Some(self.build_token("=", TokenType::Greater))
+                    Some(self.build_token(">", TokenType::Greater))
                 }
             }
             Some("\"") => Some(self.string()),
             Some(alpha) if is_alpha(alpha) => Some(self.identifier_or_keyword()),
             Some(digit) if is_digit(digit) => Some(self.number()),
-            Some(_) => panic!("Unexpected character"),
+            Some(_) => {
+                Some(self.build_token(&self.code[self.start..self.current], TokenType::Error))
+            }
             None => {
                 self.is_over = true;
                 Some(self.build_token("", TokenType::Eof))
@@ -259,6 +325,7 @@ impl<'code> Iterator for ScannerIterator<'code> {
     }
 }
 
+#[allow(clippy::match_like_matches_macro)]
 fn is_digit(possible_digit: &str) -> bool {
     match possible_digit {
         "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" => true,
@@ -266,6 +333,7 @@ fn is_digit(possible_digit: &str) -> bool {
     }
 }
 
+#[allow(clippy::match_like_matches_macro)]
 fn is_alpha(possible_alpha: &str) -> bool {
     match possible_alpha {
         "a" | "b" | "c" | "d" | "e" | "f" | "g" | "h" | "i" | "j" | "k" | "l" | "m" | "n" | "o"
@@ -293,4 +361,68 @@ mod tests {
         let second_operand = tokens.next().unwrap();
         assert_eq!(second_operand.code, "2");
     }
+
+    #[test]
+    fn test_comments() {
+        let code = "1 // one\n// two\n/ 2";
+
+        let mut scanner = Scanner::new();
+        let codes: Vec<&str> = scanner.scan(code).map(|token| token.code).collect();
+        assert_eq!(codes, ["1", "/", "2", ""]);
+
+        let tokens: Vec<Token> = scanner.scan_with_comments(code).collect();
+        let codes: Vec<&str> = tokens.iter().map(|token| token.code).collect();
+        assert_eq!(codes, ["1", "// one", "// two", "/", "2", ""]);
+        assert_eq!(tokens[2].token_type, TokenType::Comment);
+        assert_eq!(tokens[2].line, 2);
+    }
+
+    #[test]
+    fn test_columns() {
+        let mut scanner = Scanner::new();
+        let positions: Vec<(usize, usize)> = scanner
+            .scan("1 +\n  \"a\nb\" >=\t2")
+            .map(|token| (token.start_line(), token.column))
+            .collect();
+
+        assert_eq!(positions, [(1, 1), (1, 3), (2, 3), (3, 4), (3, 7), (3, 8)]);
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let mut scanner = Scanner::new();
+        let tokens: Vec<Token> = scanner.scan("\"héllo\" // ünïcode\n€").collect();
+
+        assert_eq!(tokens[0].code, "\"héllo\"");
+        assert_eq!(tokens[1].token_type, TokenType::Error);
+        assert_eq!(tokens[1].code, "€");
+        assert_eq!(tokens[1].error_message(), Some("Unexpected character."));
+        assert_eq!((tokens[1].line, tokens[1].column), (2, 1));
+    }
+
+    #[test]
+    fn test_trivia_is_lossless() {
+        let code = "  // start\r\n(1 +\t\"two\nlines\") # € >=// end\n\n\"open";
+
+        let mut scanner = Scanner::new();
+        let tokens: Vec<Token> = scanner.scan_with_trivia(code).collect();
+        let rebuilt: String = tokens.iter().map(|token| token.code).collect();
+        assert_eq!(rebuilt, code);
+
+        let types: Vec<TokenType> = tokens.iter().take(4).map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Whitespace,
+                TokenType::Comment,
+                TokenType::Whitespace,
+                TokenType::LeftParen
+            ]
+        );
+        assert_eq!((tokens[3].line, tokens[3].column), (2, 1));
+
+        let last = &tokens[tokens.len() - 2];
+        assert_eq!(last.code, "\"open");
+        assert_eq!(last.error_message(), Some("Unterminated string."));
+    }
 }
@@ -0,0 +1,14 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::compiler::compile;
+use crate::rlox::vm::InterpretError;
+use crate::rlox::vm::Vm;
+
+/// Compiles and runs a whole script. Unlike the REPL, the value the script
+/// evaluates to is discarded instead of printed.
+pub fn run_script(vm: &mut Vm, code: &str) -> Result<(), InterpretError> {
+    let mut chunk = Chunk::new();
+    compile(code, &mut chunk)?;
+    vm.interpret(&chunk)?;
+
+    Ok(())
+}
@@ -0,0 +1,286 @@
+use crate::rlox::convert::{ConversionError, FromLox, FromLoxArgs, IntoLox};
+use crate::rlox::value::{Obj, ObjValue, Value};
+use crate::rlox::vm::Vm;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Defines the functions every VM starts with, unless it's created with
+/// `Vm::bare`:
+///
+/// - `clock()`: the number of seconds since the Unix epoch
+/// - `type(x)`: the name of the type of a value
+/// - `str(x)`: a value as it would be displayed
+/// - `num(x)`: a string parsed as a number, or nil if it isn't one
+/// - `len(x)`: the number of characters in a string, elements in a list or
+///   keys in a map
+/// - `input()`: a line read from the standard input, or nil at its end
+/// - `exit(code)`: stops the program with the given exit code, from 0 to 255
+/// - `sqrt(x)`, `floor(x)`, `ceil(x)`, `abs(x)`, `sin(x)`, `cos(x)`,
+///   `pow(x, y)`, and `min(...)` and `max(...)` over one or more numbers
+/// - `random()`: a number between 0 (included) and 1 (excluded), from a
+///   generator seeded with the time unless `seed(x)` makes it repeatable
+pub(crate) fn define_stdlib(vm: &mut Vm) {
+    vm.define_native(
+        "clock",
+        typed(|()| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs_f64())
+                .unwrap_or(0.0)
+        }),
+    );
+    vm.define_native("type", typed(|(value,): (Value,)| value.type_name()));
+    vm.define_native("str", typed(|(value,): (Value,)| value.to_string()));
+    vm.define_native("num", |arguments| {
+        match self::arguments::<(Value,)>(arguments)? {
+            (Value::Number(number),) => Ok(Value::Number(number)),
+            (value,) => match String::from_lox(&value) {
+                Ok(string) => Ok(string.trim().parse::<f64>().ok().into_lox()),
+                Err(_) => Err(format!(
+                    "Expected number or string but found {}",
+                    value.type_name()
+                )),
+            },
+        }
+    });
+    vm.define_native("len", |arguments| {
+        match self::arguments::<(Value,)>(arguments)? {
+            (Value::Obj(Obj {
+                value: ObjValue::List(list),
+            }),) => Ok(list.borrow().len().into_lox()),
+            (Value::Obj(Obj {
+                value: ObjValue::Map(map),
+            }),) => Ok(map.borrow().len().into_lox()),
+            (value,) => match String::from_lox(&value) {
+                Ok(string) => Ok(string.chars().count().into_lox()),
+                Err(_) => Err(format!(
+                    "Expected string, list or map but found {}",
+                    value.type_name()
+                )),
+            },
+        }
+    });
+    vm.define_native("input", |arguments| {
+        let () = self::arguments(arguments)?;
+        let mut line = String::new();
+
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => Ok(Value::Nil),
+            Ok(_) => {
+                let length = line.trim_end_matches(&['\n', '\r'][..]).len();
+                line.truncate(length);
+                Ok(Value::from(line))
+            }
+            Err(error) => Err(error.to_string()),
+        }
+    });
+    // Exiting is left to whoever runs the VM, so that they can still clean
+    // up (e.g. write a profile). The VM stops once the call returns.
+    let exit_code = vm.exit_code();
+    vm.define_native(
+        "exit",
+        typed(move |(code,): (u8,)| exit_code.set(Some(code))),
+    );
+
+    vm.define_native("sqrt", typed(|(x,): (f64,)| x.sqrt()));
+    vm.define_native("floor", typed(|(x,): (f64,)| x.floor()));
+    vm.define_native("ceil", typed(|(x,): (f64,)| x.ceil()));
+    vm.define_native("abs", typed(|(x,): (f64,)| x.abs()));
+    vm.define_native("sin", typed(|(x,): (f64,)| x.sin()));
+    vm.define_native("cos", typed(|(x,): (f64,)| x.cos()));
+    vm.define_native("pow", typed(|(x, y): (f64, f64)| x.powf(y)));
+    vm.define_native("min", |arguments| reduce_numbers(arguments, f64::min));
+    vm.define_native("max", |arguments| reduce_numbers(arguments, f64::max));
+
+    let random = Rc::new(Random::new(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0),
+    ));
+
+    let generator = Rc::clone(&random);
+    vm.define_native("random", typed(move |()| generator.next()));
+    vm.define_native(
+        "seed",
+        typed(move |(seed,): (f64,)| random.seed(seed.to_bits())),
+    );
+}
+
+/// Converts the arguments of a native, with the error the VM should report
+/// if they don't fit.
+pub(crate) fn arguments<A: FromLoxArgs>(arguments: &[Value]) -> Result<A, String> {
+    A::from_lox_args(arguments).map_err(|error| error.to_string())
+}
+
+/// Wraps a Rust function into a native, converting its arguments and its
+/// result.
+pub(crate) fn typed<A, R, F>(function: F) -> impl Fn(&[Value]) -> Result<Value, String>
+where
+    A: FromLoxArgs,
+    R: IntoLox,
+    F: Fn(A) -> R,
+{
+    move |arguments| Ok(function(self::arguments(arguments)?).into_lox())
+}
+
+fn reduce_numbers(arguments: &[Value], operator: fn(f64, f64) -> f64) -> Result<Value, String> {
+    let numbers = arguments
+        .iter()
+        .enumerate()
+        .map(|(position, value)| {
+            f64::from_lox(value).map_err(|error| ConversionError::Argument {
+                position,
+                error: Box::new(error),
+            })
+        })
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|error| error.to_string())?;
+
+    numbers
+        .into_iter()
+        .reduce(operator)
+        .map(Value::Number)
+        .ok_or_else(|| String::from("Expected at least 1 argument but got 0"))
+}
+
+/// A SplitMix64 generator. It's not suitable for anything security related,
+/// but it's fast, small and any seed works.
+struct Random {
+    state: Cell<u64>,
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random {
+            state: Cell::new(seed),
+        }
+    }
+
+    fn seed(&self, seed: u64) {
+        self.state.set(seed);
+    }
+
+    fn next(&self) -> f64 {
+        let state = self.state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.state.set(state);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        // The top 53 bits fill the mantissa of a number in [0, 1).
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rlox::chunk::Chunk;
+    use crate::rlox::compiler::compile;
+    use crate::rlox::vm::InterpretError;
+
+    fn evaluate(vm: &mut Vm, code: &str) -> Result<Value, InterpretError> {
+        let mut chunk = Chunk::new();
+        compile(code, &mut chunk)?;
+        vm.interpret(&chunk)
+    }
+
+    fn assert_evaluates_to(code: &str, expected: Value) {
+        assert_eq!(evaluate(&mut Vm::new(), code), Ok(expected), "for {}", code);
+    }
+
+    #[test]
+    fn test_core_functions() {
+        assert_evaluates_to(
+            "type(1) + type(\"a\") + type(nil)",
+            Value::from("numberstringnil"),
+        );
+        assert_evaluates_to("type(clock)", Value::from("function"));
+        assert_evaluates_to("str(1.5) + str(true)", Value::from("1.5true"));
+        assert_evaluates_to("num(\" 2.5 \") * 2", Value::Number(5.0));
+        assert_evaluates_to("num(\"two\")", Value::Nil);
+        assert_evaluates_to("num(3)", Value::Number(3.0));
+        assert_evaluates_to("len(\"héllo\")", Value::Number(5.0));
+        assert_evaluates_to("len([1, [2, 3]])", Value::Number(2.0));
+        assert_evaluates_to("len({1: 2, 1: 3, 0: 4})", Value::Number(2.0));
+        assert_evaluates_to("clock() > 0", Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_math() {
+        assert_evaluates_to("sqrt(16) + pow(2, 10)", Value::Number(1028.0));
+        assert_evaluates_to("floor(-1.5) + ceil(1.2) + abs(-3)", Value::Number(3.0));
+        assert_evaluates_to("sin(0) + cos(0)", Value::Number(1.0));
+        assert_evaluates_to("min(3, 1, 2) - max(3, 1, 2)", Value::Number(-2.0));
+        assert_evaluates_to("max(4)", Value::Number(4.0));
+    }
+
+    #[test]
+    fn test_random_is_repeatable_once_seeded() {
+        let mut vm = Vm::new();
+
+        let sequence = |vm: &mut Vm| -> Vec<Value> {
+            evaluate(vm, "seed(42)").unwrap();
+            (0..100)
+                .map(|_| evaluate(vm, "random()").unwrap())
+                .collect()
+        };
+
+        let first = sequence(&mut vm);
+        assert_eq!(first, sequence(&mut vm));
+        assert!(first
+            .iter()
+            .all(|value| matches!(value, Value::Number(n) if (0.0..1.0).contains(n))));
+        assert_ne!(first[0], first[1]);
+    }
+
+    #[test]
+    fn test_invalid_arguments_are_runtime_errors() {
+        let mut vm = Vm::new();
+
+        for code in &[
+            "sqrt(\"a\")",
+            "pow(1)",
+            "min()",
+            "max(1, nil)",
+            "num(true)",
+            "len(1)",
+            "exit(256)",
+            "exit(-1)",
+            "exit(1.5)",
+        ] {
+            assert_eq!(
+                evaluate(&mut vm, code),
+                Err(InterpretError::RuntimeError),
+                "for {}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_exit_stops_the_vm() {
+        let mut vm = Vm::new();
+
+        assert_eq!(
+            evaluate(&mut vm, "[exit(3), clock()]"),
+            Err(InterpretError::Exit(3))
+        );
+        assert!(vm.stack().is_empty());
+        assert_eq!(evaluate(&mut vm, "len([1, 2])"), Ok(Value::Number(2.0)));
+    }
+
+    #[test]
+    fn test_bare_vm_has_no_stdlib() {
+        assert_eq!(Vm::bare().globals().count(), 0);
+        assert_eq!(
+            evaluate(&mut Vm::bare(), "clock()"),
+            Err(InterpretError::RuntimeError)
+        );
+    }
+}
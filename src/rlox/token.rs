@@ -3,18 +3,43 @@ use crate::rlox::compiler::Precedence;
 #[derive(Debug)]
 pub struct Token<'code> {
     pub code: &'code str,
+    /// The line the token ends on, which is only different from the one it
+    /// starts on for strings spanning several lines.
     pub line: usize,
+    /// The column the token starts at on its first line, counting characters
+    /// from 1.
+    pub column: usize,
     pub token_type: TokenType,
 }
 
 impl<'code> Token<'code> {
-    pub fn new(code: &'code str, line: usize, token_type: TokenType) -> Token {
+    pub fn new(
+        code: &'code str,
+        line: usize,
+        column: usize,
+        token_type: TokenType,
+    ) -> Token<'code> {
         Token {
             code,
             line,
+            column,
             token_type,
         }
     }
+
+    /// The message of an error token. The code of an error token is the
+    /// part of the source the scanner couldn't make sense of.
+    pub fn error_message(&self) -> Option<&'static str> {
+        match self.token_type {
+            TokenType::Error if self.code.starts_with('"') => Some("Unterminated string."),
+            TokenType::Error => Some("Unexpected character."),
+            _ => None,
+        }
+    }
+
+    pub fn start_line(&self) -> usize {
+        self.line - self.code.matches('\n').count()
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -24,7 +49,10 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -55,6 +83,7 @@ pub enum TokenType {
     For,
     Fun,
     If,
+    Import,
     Nil,
     Or,
     Print,
@@ -65,26 +94,33 @@ pub enum TokenType {
     Var,
     While,
 
+    // Trivia, only produced when a tool asks for it.
+    Whitespace,
+    Comment,
+
     Error,
     Eof,
 }
 
 impl TokenType {
-    pub fn precedence(&self) -> Precedence {
+    pub(crate) fn precedence(&self) -> Precedence {
         match self {
-            TokenType::LeftParen => Precedence::None,
+            TokenType::LeftParen => Precedence::Call,
             TokenType::RightParen => Precedence::None,
             TokenType::LeftBrace => Precedence::None,
             TokenType::RightBrace => Precedence::None,
+            TokenType::LeftBracket => Precedence::Call,
+            TokenType::RightBracket => Precedence::None,
             TokenType::Comma => Precedence::None,
-            TokenType::Dot => Precedence::None,
+            TokenType::Colon => Precedence::None,
+            TokenType::Dot => Precedence::Call,
             TokenType::Minus => Precedence::Term,
             TokenType::Plus => Precedence::Term,
             TokenType::Semicolon => Precedence::None,
             TokenType::Slash => Precedence::Factor,
             TokenType::Star => Precedence::Factor,
             TokenType::Bang => Precedence::None,
-            TokenType::BangEqual => Precedence::None,
+            TokenType::BangEqual => Precedence::Equality,
             TokenType::Equal => Precedence::None,
             TokenType::EqualEqual => Precedence::Equality,
             TokenType::Greater => Precedence::Comparison,
@@ -101,6 +137,7 @@ impl TokenType {
             TokenType::For => Precedence::None,
             TokenType::Fun => Precedence::None,
             TokenType::If => Precedence::None,
+            TokenType::Import => Precedence::None,
             TokenType::Nil => Precedence::None,
             TokenType::Or => Precedence::None,
             TokenType::Print => Precedence::None,
@@ -110,6 +147,8 @@ impl TokenType {
             TokenType::True => Precedence::None,
             TokenType::Var => Precedence::None,
             TokenType::While => Precedence::None,
+            TokenType::Whitespace => Precedence::None,
+            TokenType::Comment => Precedence::None,
             TokenType::Error => Precedence::None,
             TokenType::Eof => Precedence::None,
         }
@@ -0,0 +1,86 @@
+use crate::rlox::chunk::Chunk;
+use crate::rlox::disassembler::{constant_literal, disassemble_instruction};
+use crate::rlox::value::Value;
+use crate::rlox::vm::Vm;
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Callbacks into the VM's execution, for tracing, debugging or profiling.
+/// All of them do nothing by default so that hooks only need to implement the
+/// events they care about.
+pub trait VmHook {
+    /// Called when a chunk starts running, with `script` as its name, or
+    /// when a native function is called.
+    fn on_call(&mut self, _name: &str) {}
+
+    /// Called right before the instruction at `index` is executed, with the
+    /// VM paused so that its stack and globals can be looked at.
+    fn on_instruction(&mut self, _vm: &Vm, _chunk: &Chunk, _index: usize) {}
+
+    /// Called when a chunk or a native function returns, with the value it
+    /// returned.
+    fn on_return(&mut self, _value: &Value) {}
+
+    /// Called when execution stops because of a runtime error.
+    fn on_runtime_error(&mut self, _message: &str, _line: usize) {}
+
+    /// Checked right after `on_instruction`. Returning true stops execution
+    /// before the instruction runs, with `InterpretError::Stopped`.
+    fn should_stop(&self) -> bool {
+        false
+    }
+}
+
+/// Prints the stack and the disassembly of every instruction to stderr, with
+/// values written the way the disassembly writes constants. Runtime errors
+/// aren't repeated, since the VM already prints them there.
+pub struct StderrTracer;
+
+impl VmHook for StderrTracer {
+    fn on_call(&mut self, name: &str) {
+        eprintln!("== {} ==", name);
+    }
+
+    fn on_instruction(&mut self, vm: &Vm, chunk: &Chunk, index: usize) {
+        let mut stderr = io::stderr();
+
+        let _ = write!(stderr, "          ");
+
+        for value in vm.stack() {
+            let _ = write!(stderr, "[ {} ]", constant_literal(value));
+        }
+
+        let _ = writeln!(stderr);
+        let _ = disassemble_instruction(chunk, index, &mut stderr);
+    }
+
+    fn on_return(&mut self, value: &Value) {
+        eprintln!("Returning {}", constant_literal(value));
+    }
+}
+
+/// Lets a hook be shared with the VM, so that whoever installed it can still
+/// look at it once the VM is done (e.g. to print a report).
+impl<T: VmHook> VmHook for Rc<RefCell<T>> {
+    fn on_call(&mut self, name: &str) {
+        self.borrow_mut().on_call(name);
+    }
+
+    fn on_instruction(&mut self, vm: &Vm, chunk: &Chunk, index: usize) {
+        self.borrow_mut().on_instruction(vm, chunk, index);
+    }
+
+    fn on_return(&mut self, value: &Value) {
+        self.borrow_mut().on_return(value);
+    }
+
+    fn on_runtime_error(&mut self, message: &str, line: usize) {
+        self.borrow_mut().on_runtime_error(message, line);
+    }
+
+    fn should_stop(&self) -> bool {
+        self.borrow().should_stop()
+    }
+}
@@ -1,3 +1,9 @@
+use crate::rlox::disassembler::constant_literal;
+use crate::rlox::map::Map;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Boolean(bool),
@@ -8,15 +14,38 @@ pub enum Value {
 
 impl Value {
     pub fn is_truthy(&self) -> bool {
-        match self {
-            Value::Nil | Value::Boolean(false) => false,
-            _ => true,
-        }
+        !matches!(self, Value::Nil | Value::Boolean(false))
     }
 
     pub fn is_falsey(&self) -> bool {
         !self.is_truthy()
     }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Nil => "nil",
+            Value::Obj(obj) => match obj.value {
+                ObjValue::String(_) => "string",
+                ObjValue::Native(_) => "function",
+                ObjValue::List(_) => "list",
+                ObjValue::Map(_) => "map",
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Boolean(boolean) => write!(f, "{}", boolean),
+            Value::Number(number) if number.is_nan() => write!(f, "nan"),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Nil => write!(f, "nil"),
+            Value::Obj(obj) => write!(f, "{}", obj),
+        }
+    }
 }
 
 impl From<bool> for Value {
@@ -39,6 +68,22 @@ impl From<&str> for Value {
     }
 }
 
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Obj(Obj {
+            value: ObjValue::List(Rc::new(RefCell::new(value))),
+        })
+    }
+}
+
+impl From<Map> for Value {
+    fn from(value: Map) -> Self {
+        Value::Obj(Obj {
+            value: ObjValue::Map(Rc::new(RefCell::new(value))),
+        })
+    }
+}
+
 impl From<String> for Value {
     fn from(value: String) -> Self {
         Value::Obj(Obj {
@@ -50,9 +95,136 @@ impl From<String> for Value {
 #[derive(Debug, PartialEq, Clone)]
 pub enum ObjValue {
     String(String),
+    Native(NativeFunction),
+    /// Lists are shared: copying the value gives another reference to the
+    /// same list, which sees the changes made through any of them. They're
+    /// equal when their elements are.
+    List(Rc<RefCell<Vec<Value>>>),
+    /// Maps are shared like lists.
+    Map(Rc<RefCell<Map>>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Obj {
     pub value: ObjValue,
 }
+
+impl fmt::Display for Obj {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            ObjValue::String(string) => write!(f, "{}", string),
+            ObjValue::Native(native) => write!(f, "<native fn {}>", native.name),
+            ObjValue::List(list) => {
+                write!(f, "[")?;
+
+                for (index, element) in list.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", constant_literal(element))?;
+                }
+
+                write!(f, "]")
+            }
+            ObjValue::Map(map) => {
+                write!(f, "{{")?;
+
+                for (index, (key, value)) in map.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}: {}", constant_literal(key), constant_literal(value))?;
+                }
+
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// The signature of functions implemented in Rust. They get their arguments
+/// as a slice and report errors with a message, which the VM turns into a
+/// runtime error.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A function implemented in Rust that Lox code can call.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, function: F) -> NativeFunction
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        NativeFunction {
+            name: String::from(name),
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn call(&self, arguments: &[Value]) -> Result<Value, String> {
+        (self.function)(arguments)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+/// Natives are only equal to themselves, like functions in Lox.
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl From<NativeFunction> for Value {
+    fn from(value: NativeFunction) -> Self {
+        Value::Obj(Obj {
+            value: ObjValue::Native(value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Number(3.0).to_string(), "3");
+        assert_eq!(Value::Number(0.5).to_string(), "0.5");
+        assert_eq!(Value::Number(f64::NAN).to_string(), "nan");
+        assert_eq!(Value::Number(-f64::INFINITY).to_string(), "-inf");
+        assert_eq!(Value::Boolean(true).to_string(), "true");
+        assert_eq!(Value::Nil.to_string(), "nil");
+        assert_eq!(Value::from("hi").to_string(), "hi");
+        assert_eq!(
+            Value::from(NativeFunction::new("f", |_| Ok(Value::Nil))).to_string(),
+            "<native fn f>"
+        );
+        assert_eq!(
+            Value::from(vec![
+                Value::Number(1.0),
+                Value::from("a"),
+                Value::from(vec![Value::Nil])
+            ])
+            .to_string(),
+            "[1, \"a\", [nil]]"
+        );
+
+        let mut map = Map::new();
+        map.insert(Value::from("a"), Value::from(vec![])).unwrap();
+        map.insert(Value::Number(1.0), Value::Boolean(false))
+            .unwrap();
+        assert_eq!(Value::from(map).to_string(), "{\"a\": [], 1: false}");
+        assert_eq!(Value::from(Map::new()).to_string(), "{}");
+    }
+}
@@ -1,42 +1,152 @@
 use crate::rlox::chunk::Chunk;
-use crate::rlox::disassembler::disassemble_instruction;
 use crate::rlox::instruction::Instruction;
-use crate::rlox::value::{ObjValue, Value};
+use crate::rlox::map::Map;
+use crate::rlox::methods::{get_method, resolve_index, slice_bounds};
+use crate::rlox::stdlib::define_stdlib;
+use crate::rlox::trace::VmHook;
+use crate::rlox::value::{NativeFunction, Obj, ObjValue, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 pub struct Vm {
     ip: usize,
     stack: Vec<Value>,
+    globals: HashMap<String, Value>,
+    hook: Option<Box<dyn VmHook>>,
+    /// Set by the `exit` native, which can't stop the VM by itself.
+    exit_code: Rc<Cell<Option<u8>>>,
 }
 
 impl Vm {
+    /// A VM with the standard library already defined as globals.
     pub fn new() -> Vm {
+        let mut vm = Vm::bare();
+        define_stdlib(&mut vm);
+        vm
+    }
+
+    /// A VM without any globals, for embedders that want to choose exactly
+    /// what scripts have access to.
+    pub fn bare() -> Vm {
         Vm {
             ip: 0,
             stack: vec![],
+            globals: HashMap::new(),
+            hook: None,
+            exit_code: Rc::new(Cell::new(None)),
         }
     }
 
-    pub fn interpret(&mut self, chunk: &Chunk) -> Result<(), InterpretError> {
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(String::from(name), value);
+    }
+
+    /// Defines a global function implemented in Rust.
+    pub fn define_native<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        self.define_global(name, Value::from(NativeFunction::new(name, function)));
+    }
+
+    /// Installs a hook that gets notified as the VM executes code, replacing
+    /// any previously installed one.
+    pub fn set_hook(&mut self, hook: Box<dyn VmHook>) {
+        self.hook = Some(hook);
+    }
+
+    /// Where natives can ask the VM to stop with an exit code, which it does
+    /// with `InterpretError::Exit` once they return.
+    pub(crate) fn exit_code(&self) -> Rc<Cell<Option<u8>>> {
+        Rc::clone(&self.exit_code)
+    }
+
+    pub fn take_hook(&mut self) -> Option<Box<dyn VmHook>> {
+        self.hook.take()
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.globals.iter()
+    }
+
+    /// Runs a chunk to completion, returning the value it evaluated to.
+    pub fn interpret(&mut self, chunk: &Chunk) -> Result<Value, InterpretError> {
         self.ip = 0;
-        self.run(&chunk)?;
-        Ok(())
+
+        if let Some(hook) = self.hook.as_mut() {
+            hook.on_call("script");
+        }
+
+        // The same VM can be used to interpret several chunks (e.g. each line
+        // of a REPL session), so a failed run shouldn't leave behind values
+        // that the next chunk would find on the stack.
+        let result = self.run(chunk);
+
+        if result.is_err() {
+            self.reset_stack();
+        }
+
+        result
+    }
+
+    fn reset_stack(&mut self) {
+        self.stack.clear();
+    }
+
+    fn runtime_error(&mut self, chunk: &Chunk, message: &str) -> InterpretError {
+        let line = *chunk.line_at(self.ip - 1);
+
+        if let Some(hook) = self.hook.as_mut() {
+            hook.on_runtime_error(message, line);
+        }
+
+        eprintln!("{}", message);
+        eprintln!("[line {}] in script", line);
+        InterpretError::RuntimeError
     }
 
-    fn run(&mut self, chunk: &Chunk) -> Result<(), InterpretError> {
+    /// Gets the right operand of a binary operation. It's usually on top of
+    /// the stack, but instructions fused with an `OpConstant` have it as an
+    /// operand instead.
+    fn right_operand(&mut self, chunk: &Chunk, instruction: Option<&Instruction>) -> Value {
+        match instruction {
+            Some(Instruction::OpAddConstant(index))
+            | Some(Instruction::OpSubtractConstant(index))
+            | Some(Instruction::OpMultiplyConstant(index))
+            | Some(Instruction::OpDivideConstant(index)) => chunk.constant_at(*index).clone(),
+            _ => self
+                .stack
+                .pop()
+                .expect("Tried to pop element off empty stack"),
+        }
+    }
+
+    fn run(&mut self, chunk: &Chunk) -> Result<Value, InterpretError> {
         loop {
             let instruction = chunk.instruction_at(self.ip);
             self.ip += 1;
 
-            if cfg!(debug_assertions) {
-                print!("          ");
+            // The hook is taken out while it runs so that it can be given
+            // the whole VM to look at.
+            if let Some(mut hook) = self.hook.take() {
+                hook.on_instruction(self, chunk, self.ip - 1);
+                let should_stop = hook.should_stop();
+                self.hook = Some(hook);
 
-                for value in self.stack.iter() {
-                    print!("[ {:?} ]", value);
+                if should_stop {
+                    return Err(InterpretError::Stopped);
                 }
 
-                println!("");
-
-                disassemble_instruction(chunk, self.ip - 1);
+                // The hook may have called natives of this VM, like a
+                // debugger evaluating `exit(0)`.
+                if let Some(code) = self.exit_code.take() {
+                    return Err(InterpretError::Exit(code));
+                }
             }
 
             match instruction {
@@ -45,8 +155,12 @@ impl Vm {
                         .stack
                         .pop()
                         .expect("Tried to pop element off empty stack");
-                    println!("Returning {:?}", value);
-                    return Ok(());
+
+                    if let Some(hook) = self.hook.as_mut() {
+                        hook.on_return(&value);
+                    }
+
+                    return Ok(value);
                 }
                 Some(Instruction::OpConstant(index)) => {
                     let value = chunk.constant_at(*index);
@@ -64,22 +178,21 @@ impl Vm {
                         .pop()
                         .expect("Tried to pop element of an empty stack");
 
-                    // TODO: Print runtime error
                     match next_value {
                         Value::Number(number) => {
                             let result = -number;
                             self.stack.push(Value::from(result));
                         }
-                        _ => return Err(InterpretError::RuntimeError),
+                        _ => return Err(self.runtime_error(chunk, "Operand must be a number.")),
                     }
                 }
                 Some(Instruction::OpSubtract)
                 | Some(Instruction::OpMultiply)
-                | Some(Instruction::OpDivide) => {
-                    let b = self
-                        .stack
-                        .pop()
-                        .expect("Tried to pop element off empty stack");
+                | Some(Instruction::OpDivide)
+                | Some(Instruction::OpSubtractConstant(_))
+                | Some(Instruction::OpMultiplyConstant(_))
+                | Some(Instruction::OpDivideConstant(_)) => {
+                    let b = self.right_operand(chunk, instruction);
                     let a = self
                         .stack
                         .pop()
@@ -88,25 +201,22 @@ impl Vm {
                     match (b, a) {
                         (Value::Number(b), Value::Number(a)) => {
                             let result = match instruction {
-                                Some(Instruction::OpSubtract) => a - b,
-                                Some(Instruction::OpDivide) => a / b,
-                                Some(Instruction::OpMultiply) => a * b,
+                                Some(Instruction::OpSubtract)
+                                | Some(Instruction::OpSubtractConstant(_)) => a - b,
+                                Some(Instruction::OpDivide)
+                                | Some(Instruction::OpDivideConstant(_)) => a / b,
+                                Some(Instruction::OpMultiply)
+                                | Some(Instruction::OpMultiplyConstant(_)) => a * b,
                                 _ => unreachable!(),
                             };
 
                             self.stack.push(Value::Number(result));
                         }
-                        _ => {
-                            // TODO: Log runtime error
-                            return Err(InterpretError::RuntimeError);
-                        }
+                        _ => return Err(self.runtime_error(chunk, "Operands must be numbers.")),
                     }
                 }
-                Some(Instruction::OpAdd) => {
-                    let b = self
-                        .stack
-                        .pop()
-                        .expect("Tried to pop element off empty stack");
+                Some(Instruction::OpAdd) | Some(Instruction::OpAddConstant(_)) => {
+                    let b = self.right_operand(chunk, instruction);
                     let a = self
                         .stack
                         .pop()
@@ -114,14 +224,19 @@ impl Vm {
 
                     match (b, a) {
                         (Value::Number(b), Value::Number(a)) => self.stack.push(Value::from(a + b)),
-                        (Value::Obj(b), Value::Obj(a)) => match (b.value, a.value) {
-                            (ObjValue::String(b), ObjValue::String(a)) => {
-                                self.stack.push(Value::from(format!("{}{}", a, b)))
-                            }
-                        },
+                        (
+                            Value::Obj(Obj {
+                                value: ObjValue::String(b),
+                            }),
+                            Value::Obj(Obj {
+                                value: ObjValue::String(a),
+                            }),
+                        ) => self.stack.push(Value::from(format!("{}{}", a, b))),
                         _ => {
-                            // TODO: Log runtime error
-                            return Err(InterpretError::RuntimeError);
+                            return Err(self.runtime_error(
+                                chunk,
+                                "Operands must be two numbers or two strings.",
+                            ))
                         }
                     }
                 }
@@ -135,7 +250,7 @@ impl Vm {
                         .expect("Tried to pop element off empty stack");
                     self.stack.push(Value::from(value.is_falsey()));
                 }
-                Some(Instruction::OpEqual) => {
+                Some(Instruction::OpEqual) | Some(Instruction::OpNotEqual) => {
                     let b = self
                         .stack
                         .pop()
@@ -144,9 +259,20 @@ impl Vm {
                         .stack
                         .pop()
                         .expect("Tried to pop element off empty stack");
-                    self.stack.push(Value::from(b == a));
+                    let equal = b == a;
+
+                    match instruction {
+                        Some(Instruction::OpEqual) => self.stack.push(Value::from(equal)),
+                        _ => self.stack.push(Value::from(!equal)),
+                    }
                 }
-                Some(Instruction::OpGreater) | Some(Instruction::OpLess) => {
+                // These are all separate instructions, rather than some of
+                // them being the negation of another, because with NaN around
+                // `a >= b` isn't the same as `!(a < b)`.
+                Some(Instruction::OpGreater)
+                | Some(Instruction::OpGreaterEqual)
+                | Some(Instruction::OpLess)
+                | Some(Instruction::OpLessEqual) => {
                     let b = self
                         .stack
                         .pop()
@@ -160,16 +286,215 @@ impl Vm {
                         (Value::Number(b), Value::Number(a)) => {
                             let result = match instruction {
                                 Some(Instruction::OpGreater) => a > b,
+                                Some(Instruction::OpGreaterEqual) => a >= b,
                                 Some(Instruction::OpLess) => a < b,
+                                Some(Instruction::OpLessEqual) => a <= b,
                                 _ => unreachable!(),
                             };
 
                             self.stack.push(Value::from(result));
                         }
+                        _ => return Err(self.runtime_error(chunk, "Operands must be numbers.")),
+                    }
+                }
+                Some(Instruction::OpGetGlobal(index)) => {
+                    let name = match chunk.constant_at(*index) {
+                        Value::Obj(Obj {
+                            value: ObjValue::String(name),
+                        }) => name,
+                        _ => panic!("Global names are always string constants"),
+                    };
+
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => {
+                            let message = format!("Undefined variable '{}'.", name);
+                            return Err(self.runtime_error(chunk, &message));
+                        }
+                    }
+                }
+                Some(Instruction::OpCall(arguments_count)) => {
+                    let callee_index = self.stack.len() - arguments_count - 1;
+
+                    let native = match &self.stack[callee_index] {
+                        Value::Obj(Obj {
+                            value: ObjValue::Native(native),
+                        }) => native.clone(),
                         _ => {
-                            // TODO: Log runtime error
-                            return Err(InterpretError::RuntimeError);
+                            return Err(
+                                self.runtime_error(chunk, "Can only call functions and classes.")
+                            )
                         }
+                    };
+
+                    let arguments = self.stack.split_off(callee_index + 1);
+                    self.stack.pop();
+
+                    if let Some(hook) = self.hook.as_mut() {
+                        hook.on_call(&native.name);
+                    }
+
+                    match native.call(&arguments) {
+                        Ok(value) => {
+                            if let Some(hook) = self.hook.as_mut() {
+                                hook.on_return(&value);
+                            }
+
+                            if let Some(code) = self.exit_code.take() {
+                                return Err(InterpretError::Exit(code));
+                            }
+
+                            self.stack.push(value);
+                        }
+                        Err(message) => {
+                            let message = format!("{}: {}", native.name, message);
+                            return Err(self.runtime_error(chunk, &message));
+                        }
+                    }
+                }
+                Some(Instruction::OpBuildList(elements_count)) => {
+                    let elements = self.stack.split_off(self.stack.len() - elements_count);
+                    self.stack.push(Value::from(elements));
+                }
+                Some(Instruction::OpBuildMap(entries_count)) => {
+                    let mut entries = self
+                        .stack
+                        .split_off(self.stack.len() - 2 * entries_count)
+                        .into_iter();
+                    let mut map = Map::new();
+
+                    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                        if let Err(message) = map.insert(key, value) {
+                            return Err(self.runtime_error(chunk, &message));
+                        }
+                    }
+
+                    self.stack.push(Value::from(map));
+                }
+                Some(Instruction::OpGetIndex) => {
+                    let index = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+                    let object = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+
+                    let element = match &object {
+                        Value::Obj(Obj {
+                            value: ObjValue::List(list),
+                        }) => {
+                            let list = list.borrow();
+                            resolve_index(&index, list.len()).map(|index| list[index].clone())
+                        }
+                        Value::Obj(Obj {
+                            value: ObjValue::Map(map),
+                        }) => map
+                            .borrow()
+                            .get(&index)
+                            .map(|value| value.cloned().unwrap_or(Value::Nil)),
+                        Value::Obj(Obj {
+                            value: ObjValue::String(string),
+                        }) => resolve_index(&index, string.chars().count()).map(|index| {
+                            Value::from(string.chars().nth(index).unwrap().to_string())
+                        }),
+                        _ => Err(format!("Can't index a {}", object.type_name())),
+                    };
+
+                    match element {
+                        Ok(element) => self.stack.push(element),
+                        Err(message) => return Err(self.runtime_error(chunk, &message)),
+                    }
+                }
+                Some(Instruction::OpSetIndex) => {
+                    let value = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+                    let index = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+                    let object = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+
+                    let result = match &object {
+                        Value::Obj(Obj {
+                            value: ObjValue::List(list),
+                        }) => {
+                            let mut list = list.borrow_mut();
+                            resolve_index(&index, list.len())
+                                .map(|index| list[index] = value.clone())
+                        }
+                        Value::Obj(Obj {
+                            value: ObjValue::Map(map),
+                        }) => map.borrow_mut().insert(index, value.clone()),
+                        Value::Obj(Obj {
+                            value: ObjValue::String(_),
+                        }) => Err(String::from("Strings can't be changed")),
+                        _ => Err(format!("Can't index a {}", object.type_name())),
+                    };
+
+                    match result {
+                        Ok(()) => self.stack.push(value),
+                        Err(message) => return Err(self.runtime_error(chunk, &message)),
+                    }
+                }
+                Some(Instruction::OpSlice) => {
+                    let end = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+                    let start = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+                    let object = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+
+                    let slice = match &object {
+                        Value::Obj(Obj {
+                            value: ObjValue::List(list),
+                        }) => {
+                            let list = list.borrow();
+                            slice_bounds(&start, &end, list.len())
+                                .map(|range| Value::from(list[range].to_vec()))
+                        }
+                        Value::Obj(Obj {
+                            value: ObjValue::String(string),
+                        }) => slice_bounds(&start, &end, string.chars().count()).map(|range| {
+                            let slice: String =
+                                string.chars().skip(range.start).take(range.len()).collect();
+                            Value::from(slice)
+                        }),
+                        _ => Err(format!("Can't slice a {}", object.type_name())),
+                    };
+
+                    match slice {
+                        Ok(slice) => self.stack.push(slice),
+                        Err(message) => return Err(self.runtime_error(chunk, &message)),
+                    }
+                }
+                Some(Instruction::OpGetProperty(index)) => {
+                    let receiver = self
+                        .stack
+                        .pop()
+                        .expect("Tried to pop element off empty stack");
+                    let name = match chunk.constant_at(*index) {
+                        Value::Obj(Obj {
+                            value: ObjValue::String(name),
+                        }) => name,
+                        _ => panic!("Property names are always string constants"),
+                    };
+
+                    match get_method(&receiver, name) {
+                        Ok(method) => self.stack.push(Value::from(method)),
+                        Err(message) => return Err(self.runtime_error(chunk, &message)),
                     }
                 }
                 None => return Err(InterpretError::RuntimeError),
@@ -178,15 +503,57 @@ impl Vm {
     }
 }
 
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum InterpretError {
     CompileError,
     RuntimeError,
+    /// A hook asked the VM to stop, e.g. a debugger that was told to quit.
+    Stopped,
+    /// The program called `exit` with this code.
+    Exit(u8),
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rlox::compiler::compile;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct RecordingHook {
+        events: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl VmHook for RecordingHook {
+        fn on_call(&mut self, name: &str) {
+            self.events.borrow_mut().push(format!("call {}", name));
+        }
+
+        fn on_instruction(&mut self, vm: &Vm, chunk: &Chunk, index: usize) {
+            let name = chunk.instruction_at(index).unwrap().name();
+            self.events.borrow_mut().push(format!(
+                "{} with {} on the stack",
+                name,
+                vm.stack().len()
+            ));
+        }
+
+        fn on_return(&mut self, value: &Value) {
+            self.events.borrow_mut().push(format!("return {}", value));
+        }
+
+        fn on_runtime_error(&mut self, message: &str, line: usize) {
+            self.events
+                .borrow_mut()
+                .push(format!("error at {}: {}", line, message));
+        }
+    }
 
     #[test]
     fn test_empty_stack_after_binary_operation() {
@@ -219,4 +586,261 @@ mod tests {
 
         assert_eq!(vm.stack.len(), 0);
     }
+
+    #[test]
+    fn test_vm_is_reusable_after_runtime_error() {
+        let mut vm = Vm::new();
+
+        let mut failing_chunk = Chunk::new();
+        let constant_index = failing_chunk.add_constant(Value::Number(1.0));
+        failing_chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        failing_chunk.add_instruction(Instruction::OpTrue, 1);
+        failing_chunk.add_instruction(Instruction::OpAdd, 1);
+        failing_chunk.add_instruction(Instruction::OpReturn, 1);
+
+        assert_eq!(
+            vm.interpret(&failing_chunk),
+            Err(InterpretError::RuntimeError)
+        );
+        assert_eq!(vm.stack.len(), 0);
+
+        let mut chunk = Chunk::new();
+        let constant_index = chunk.add_constant(Value::Number(2.0));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(Instruction::OpReturn, 1);
+
+        vm.interpret(&chunk).expect("Error running chunk");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_interpret_returns_result() {
+        let mut vm = Vm::new();
+        let mut chunk = Chunk::new();
+
+        let constant_index = chunk.add_constant(Value::Number(2.0));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(Instruction::OpNegate, 1);
+        chunk.add_instruction(Instruction::OpReturn, 1);
+
+        assert_eq!(vm.interpret(&chunk), Ok(Value::Number(-2.0)));
+    }
+
+    #[test]
+    fn test_hook_sees_execution() {
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut vm = Vm::new();
+        vm.set_hook(Box::new(RecordingHook {
+            events: Rc::clone(&events),
+        }));
+
+        let mut chunk = Chunk::new();
+        let constant_index = chunk.add_constant(Value::Number(2.0));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(Instruction::OpNegate, 1);
+        chunk.add_instruction(Instruction::OpReturn, 1);
+        vm.interpret(&chunk).expect("Error running chunk");
+
+        let mut failing_chunk = Chunk::new();
+        failing_chunk.add_instruction(Instruction::OpTrue, 3);
+        failing_chunk.add_instruction(Instruction::OpNegate, 3);
+        failing_chunk.add_instruction(Instruction::OpReturn, 3);
+        assert!(vm.interpret(&failing_chunk).is_err());
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "call script",
+                "OpConstant with 0 on the stack",
+                "OpNegate with 1 on the stack",
+                "OpReturn with 1 on the stack",
+                "return -2",
+                "call script",
+                "OpTrue with 0 on the stack",
+                "OpNegate with 1 on the stack",
+                "error at 3: Operand must be a number.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_native_calls() {
+        let mut vm = Vm::bare();
+        vm.define_native("add", |arguments| match arguments {
+            [Value::Number(a), Value::Number(b)] => Ok(Value::Number(a + b)),
+            _ => Err(String::from("Expected two numbers")),
+        });
+
+        let mut chunk = Chunk::new();
+        let name = chunk.add_constant(Value::from("add"));
+        chunk.add_instruction(Instruction::OpGetGlobal(name), 1);
+        let constant_index = chunk.add_constant(Value::Number(1.0));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        let constant_index = chunk.add_constant(Value::Number(2.0));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(Instruction::OpCall(2), 1);
+        chunk.add_instruction(Instruction::OpReturn, 1);
+
+        assert_eq!(vm.interpret(&chunk), Ok(Value::Number(3.0)));
+        assert_eq!(vm.stack.len(), 0);
+
+        for (callee, arguments_count) in &[("add", 1), ("missing", 0)] {
+            let mut chunk = Chunk::new();
+            let name = chunk.add_constant(Value::from(*callee));
+            chunk.add_instruction(Instruction::OpGetGlobal(name), 1);
+            chunk.add_instruction(Instruction::OpTrue, 1);
+            chunk.add_instruction(Instruction::OpCall(*arguments_count), 1);
+            chunk.add_instruction(Instruction::OpReturn, 1);
+
+            assert_eq!(vm.interpret(&chunk), Err(InterpretError::RuntimeError));
+            assert_eq!(vm.stack.len(), 0);
+        }
+
+        let mut chunk = Chunk::new();
+        chunk.add_instruction(Instruction::OpNil, 1);
+        chunk.add_instruction(Instruction::OpCall(0), 1);
+        chunk.add_instruction(Instruction::OpReturn, 1);
+        assert_eq!(vm.interpret(&chunk), Err(InterpretError::RuntimeError));
+    }
+
+    #[test]
+    fn test_maps() {
+        let mut vm = Vm::bare();
+
+        // {"a": 1}["a"] and {"a": 1}[true]
+        for (key, expected) in &[
+            (Value::from("a"), Value::Number(1.0)),
+            (Value::Boolean(true), Value::Nil),
+        ] {
+            let mut chunk = Chunk::new();
+            let constant_index = chunk.add_constant(Value::from("a"));
+            chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+            let constant_index = chunk.add_constant(Value::Number(1.0));
+            chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+            chunk.add_instruction(Instruction::OpBuildMap(1), 1);
+            let constant_index = chunk.add_constant(key.clone());
+            chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+            chunk.add_instruction(Instruction::OpGetIndex, 1);
+            chunk.add_instruction(Instruction::OpReturn, 1);
+
+            assert_eq!(vm.interpret(&chunk).as_ref(), Ok(expected));
+            assert_eq!(vm.stack.len(), 0);
+        }
+
+        // {[]: nil}
+        let mut chunk = Chunk::new();
+        chunk.add_instruction(Instruction::OpBuildList(0), 1);
+        chunk.add_instruction(Instruction::OpNil, 1);
+        chunk.add_instruction(Instruction::OpBuildMap(1), 1);
+        chunk.add_instruction(Instruction::OpReturn, 1);
+
+        assert_eq!(vm.interpret(&chunk), Err(InterpretError::RuntimeError));
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    #[test]
+    fn test_indexing_and_slicing_strings() {
+        let mut vm = Vm::bare();
+        let mut evaluate = |code: &str| {
+            let mut chunk = Chunk::new();
+            compile(code, &mut chunk).expect("Error compiling code");
+            vm.interpret(&chunk)
+        };
+
+        for (code, expected) in &[
+            ("\"héllo\"[1] + \"héllo\"[-1]", "éo"),
+            ("\"héllo\"[1:3]", "él"),
+            ("\"héllo\"[:-2] + \"|\" + \"héllo\"[3:]", "hél|lo"),
+            ("\"héllo\"[4:1] + \"héllo\"[10:]", ""),
+            ("\"日本語\"[-2:]", "本語"),
+        ] {
+            assert_eq!(evaluate(code), Ok(Value::from(*expected)), "for {}", code);
+        }
+
+        assert_eq!(
+            evaluate("[1, 2, 3][-2:]"),
+            Ok(Value::from(vec![Value::Number(2.0), Value::Number(3.0)]))
+        );
+
+        for code in &[
+            "\"abc\"[3]",
+            "\"abc\"[-4]",
+            "\"abc\"[0.5]",
+            "\"abc\"[0] = \"d\"",
+            "\"abc\"[\"a\":]",
+            "1[0:1]",
+        ] {
+            assert_eq!(
+                evaluate(code),
+                Err(InterpretError::RuntimeError),
+                "for {}",
+                code
+            );
+        }
+    }
+
+    fn compare(a: f64, instruction: Instruction, b: f64) -> Value {
+        let mut vm = Vm::new();
+        let mut chunk = Chunk::new();
+
+        let constant_index = chunk.add_constant(Value::Number(a));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        let constant_index = chunk.add_constant(Value::Number(b));
+        chunk.add_instruction(Instruction::OpConstant(constant_index), 1);
+        chunk.add_instruction(instruction, 1);
+        chunk.add_instruction(Instruction::OpReturn, 1);
+
+        vm.interpret(&chunk).expect("Error running chunk")
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(
+            compare(1.0, Instruction::OpGreaterEqual, 1.0),
+            Value::from(true)
+        );
+        assert_eq!(
+            compare(1.0, Instruction::OpLessEqual, 2.0),
+            Value::from(true)
+        );
+        assert_eq!(
+            compare(2.0, Instruction::OpLessEqual, 1.0),
+            Value::from(false)
+        );
+        assert_eq!(
+            compare(1.0, Instruction::OpNotEqual, 2.0),
+            Value::from(true)
+        );
+        assert_eq!(
+            compare(1.0, Instruction::OpNotEqual, 1.0),
+            Value::from(false)
+        );
+    }
+
+    #[test]
+    fn test_comparisons_with_nan() {
+        let nan = f64::NAN;
+
+        assert_eq!(
+            compare(nan, Instruction::OpGreaterEqual, 1.0),
+            Value::from(false)
+        );
+        assert_eq!(
+            compare(nan, Instruction::OpLessEqual, 1.0),
+            Value::from(false)
+        );
+        assert_eq!(
+            compare(1.0, Instruction::OpGreaterEqual, nan),
+            Value::from(false)
+        );
+        assert_eq!(
+            compare(nan, Instruction::OpGreater, nan),
+            Value::from(false)
+        );
+        assert_eq!(compare(nan, Instruction::OpEqual, nan), Value::from(false));
+        assert_eq!(
+            compare(nan, Instruction::OpNotEqual, nan),
+            Value::from(true)
+        );
+    }
 }